@@ -0,0 +1,73 @@
+// bench.rs
+// 条件判定的基准测试：造一份有很多事件的数据，对比编译前后 pick_event 的耗时。
+// 平时不跑，用 `cargo test --release conditions -- --ignored --nocapture` 运行，
+// 事件数由环境变量 BENCH_EVENTS 指定，默认 5000。
+// 脚本条件（Script）本来就是读数据时编译好的，耗时在准备作用域上，不在这里比。
+
+use std::{fmt::Write, time::{Duration, Instant}};
+
+use crate::{game::GameData, player::Player, systems::Systems, testing};
+
+const ATTRIBUTES: usize = 16;
+const ROUNDS: u32 = 200;
+
+/// 造一份数据：events 个事件都挂在 Always 上，条件是属性、时间、地点的组合
+fn synthetic(events: usize) -> String {
+    let mut toml = String::from("[calendar]\nterm_start = \"2024-02-26\"\n[[trigger]]\n");
+    for i in 0..events {
+        writeln!(toml, "evt{i} = {{ t = \"Always\" }}").unwrap();
+    }
+    for i in 0..ATTRIBUTES {
+        writeln!(toml, "[[player]]\nname = \"attr{i}\"\nmax = 100\nmin = 0\ndefault = {}\nover_max = 100\nunder_min = 0\n\
+            over_max_desc = \"\"\nunder_min_desc = \"\"", 30 + i).unwrap();
+    }
+    toml.push_str("[[maps]]\nname = \"Town\"\nconnections = []\n");
+    let weeks: Vec<String> = (1..=16).map(|week| week.to_string()).collect();
+    for i in 0..events {
+        let (a, b, c, d) = (i % ATTRIBUTES, (i + 7) % ATTRIBUTES, (i + 3) % ATTRIBUTES, (i + 11) % ATTRIBUTES);
+        let conds = [
+            format!("{{ type = \"PlayerAttribute\", attributes = {{ attr{a} = {{ at_least = {} }}, \
+                attr{b} = {{ at_most = {{ attr = \"attr{c}\" }} }} }} }}", i % 50),
+            format!("{{ type = \"Time\", start = \"06:00\", end = \"23:00\", \
+                days = [\"Mon\", \"Tue\", \"Wed\", \"Thu\", \"Fri\"], weeks = [{}] }}", weeks.join(", ")),
+            "{ type = \"Location\", locations = [\"Town\"] }".to_string(),
+            format!("\"attr{a} + attr{d} * 2 >= {} && attr.attr{c} < 100\"", i % 60),
+        ];
+        writeln!(toml, "[[events]]\nname = \"evt{i}\"\npriority = {}\nforce = false\n\
+            condition = {{ type = \"And\", conds = [{}] }}\n\
+            segments = [{{ name = \"start\", text = \"\", options = [] }}]", 1 + i % 5, conds.join(", ")).unwrap();
+    }
+    toml
+}
+
+fn time(systems: &Systems, player: &Player) -> (Duration, Option<String>) {
+    let picked = systems.trigger.pick_event(player, systems); // 先热身一轮
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        std::hint::black_box(systems.trigger.pick_event(player, systems));
+    }
+    (start.elapsed() / ROUNDS, picked)
+}
+
+#[test]
+#[ignore]
+fn conditions() {
+    let events = std::env::var("BENCH_EVENTS").ok().and_then(|n| n.parse().ok()).unwrap_or(5000);
+    let mut data: GameData = toml::from_str(&synthetic(events)).expect("生成的数据有误");
+    data.resolve().unwrap();
+    data.validate().unwrap();
+    let raw = Systems::new(&data);
+    data.compile().unwrap();
+    let compiled = Systems::new(&data);
+
+    let mut player = testing::player(&data);
+    player.trigger.insert(crate::events::triggers::Trigger::Always);
+    player.game_time = chrono::NaiveDateTime::parse_from_str("2024-03-06 10:00", "%Y-%m-%d %H:%M").unwrap();
+
+    let (before, picked_before) = time(&raw, &player);
+    let (after, picked_after) = time(&compiled, &player);
+    assert_eq!(picked_before, picked_after, "编译前后选出的事件不同");
+    println!("{events} 个事件，每轮 pick_event：");
+    println!("  未编译 {before:?}");
+    println!("  已编译 {after:?}（{:.2} 倍）", before.as_secs_f64() / after.as_secs_f64());
+}
@@ -0,0 +1,44 @@
+// 合成窗口。列出会的配方，做不了的给出原因。
+
+use egui::Context;
+
+use crate::{frontend::FromFrontend, MainApp};
+
+#[derive(Default)]
+pub struct CraftCache {
+    pub enable: bool,
+}
+
+pub fn craft_window(app: &mut MainApp, ctx: &Context) {
+    let sender = &app.backend.sender;
+    let recipes = app.backend.cache.recipes.clone().unwrap_or_default();
+
+    egui::Window::new("合成").open(&mut app.craft_cache.enable).show(ctx, |ui| {
+        if recipes.is_empty() {
+            ui.label("还不会做任何东西。");
+            return;
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for recipe in &recipes {
+                ui.push_id(&recipe.id, |ui| {
+                    ui.strong(&recipe.name);
+                    let ingredients: Vec<String> = recipe.ingredients.iter()
+                        .map(|(name, need, have)| format!("{name} {have}/{need}"))
+                        .collect();
+                    let results: Vec<String> = recipe.results.iter()
+                        .map(|(name, count)| format!("{name} ×{count}"))
+                        .collect();
+                    ui.label(format!("{} → {}", ingredients.join(" + "), results.join(" + ")));
+                    let button = ui.add_enabled(recipe.unavailable.is_none(), egui::Button::new("做"));
+                    if let Some(reason) = &recipe.unavailable {
+                        button.on_disabled_hover_text(reason);
+                    } else if button.clicked() {
+                        sender.send(FromFrontend::Craft(recipe.id.clone()))
+                            .unwrap_or_else(|_| panic!("failed to send the crafting to the backend"));
+                    }
+                });
+                ui.separator();
+            }
+        });
+    });
+}
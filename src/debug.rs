@@ -2,7 +2,7 @@
 
 use egui::Context;
 
-use crate::{frontend::{DebugFromFrontend, DebugSign, FromFrontend}, game::DataSource, MainApp};
+use crate::{frontend::{DebugFromFrontend, DebugSign, FromFrontend}, game::DataSource, ledger::{EntryKind, LedgerEntry}, MainApp};
 
 pub fn debug_window(app: &mut MainApp, ctx: &Context) {
     egui::Window::new("Debug").show(ctx, |ui| {
@@ -40,5 +40,95 @@ pub fn debug_window(app: &mut MainApp, ctx: &Context) {
                 ) }
             )).unwrap_or_else(|_| panic!("failed to send the selection to the backend"));
         }
+
+        ui.separator();
+        let seed = app.backend.cache.debug.as_ref().and_then(|debug| debug.seed);
+        ui.label(format!("随机种子：{}", seed.map_or("未知".into(), |seed| seed.to_string())));
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut app.debug_cache.seed_str);
+            let parsed = app.debug_cache.seed_str.trim().parse::<u64>().ok();
+            if ui.add_enabled(parsed.is_some(), egui::Button::new("set seed")).clicked() {
+                if let Some(seed) = parsed {
+                    app.backend.sender.send(FromFrontend::Debug(
+                        DebugFromFrontend { sign: DebugSign::SetSeed(seed) }
+                    )).unwrap_or_else(|_| panic!("failed to send the seed to the backend"));
+                }
+            }
+        });
+
+        ui.separator();
+        ui.checkbox(&mut app.debug_cache.ledger.show, "账本");
+    });
+}
+
+/// 属性、物品的变动记录，可以筛选，筛出来的可以导出给策划算数值
+pub fn ledger_window(app: &mut MainApp, ctx: &Context) {
+    let filter = &mut app.debug_cache.ledger;
+    if !filter.show { return; }
+    let entries = app.backend.cache.ledger.as_ref().map_or(&[][..], |(_, entries)| entries);
+
+    egui::Window::new("账本").open(&mut filter.show).default_width(640.).show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("ledger_kind")
+                .selected_text(match filter.kind {
+                    None => "全部",
+                    Some(EntryKind::Attribute) => "属性",
+                    Some(EntryKind::Item) => "物品",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut filter.kind, None, "全部");
+                    ui.selectable_value(&mut filter.kind, Some(EntryKind::Attribute), "属性");
+                    ui.selectable_value(&mut filter.kind, Some(EntryKind::Item), "物品");
+                });
+            ui.label("名称");
+            ui.add(egui::TextEdit::singleline(&mut filter.name).desired_width(80.));
+            ui.label("来源");
+            ui.add(egui::TextEdit::singleline(&mut filter.source).desired_width(120.));
+        });
+
+        let shown: Vec<&LedgerEntry> = entries.iter().filter(|e| {
+            filter.kind.is_none_or(|kind| e.kind == kind)
+                && e.name.contains(filter.name.trim())
+                && e.source.to_string().contains(filter.source.trim())
+        }).collect();
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{} / {} 条", shown.len(), entries.len()));
+            let export = |path: &str, text: String| match std::fs::write(path, text) {
+                Ok(()) => format!("已导出到 {path}"),
+                Err(e) => format!("导出 {path} 失败：{e}"),
+            };
+            if ui.button("导出 CSV").clicked() {
+                filter.status = export("ledger.csv", LedgerEntry::csv(&shown));
+            }
+            if ui.button("导出 JSONL").clicked() {
+                filter.status = export("ledger.jsonl", LedgerEntry::jsonl(&shown));
+            }
+            ui.label(&filter.status);
+        });
+
+        ui.separator();
+        egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+            egui::Grid::new("ledger").striped(true).show(ui, |ui| {
+                for title in ["时间", "来源", "名称", "之前", "限制前", "之后"] {
+                    ui.strong(title);
+                }
+                ui.end_row();
+                for e in &shown {
+                    ui.label(e.time.format("%m-%d %H:%M").to_string());
+                    ui.label(e.source.to_string());
+                    ui.label(&e.name);
+                    ui.label(e.before.to_string());
+                    // 被上下限截掉的才显示，一眼就能看出哪里溢出了
+                    if e.unclamped != e.after {
+                        ui.colored_label(egui::Color32::YELLOW, e.unclamped.to_string());
+                    } else {
+                        ui.label("");
+                    }
+                    ui.label(e.after.to_string());
+                    ui.end_row();
+                }
+            });
+        });
     });
 }
\ No newline at end of file
@@ -0,0 +1,126 @@
+// changes.rs
+// 修改造成的变化：属性、物品、钱、地点、时间，给玩家看“健康 -10”“获得 咖啡 ×1”之类的提示。
+// 每个修改执行前后各记一次，差别放进 player.changes，之后由前端取走。物品的增减同时记进账本。
+
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::NaiveDateTime;
+
+use crate::player::Player;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Attribute { attr: String, delta: i32 },
+    Item { item: String, delta: i64 },
+    Money { delta: i32 },
+    Location { to: String },
+    Time { minutes: i64 },
+}
+
+/// 修改前玩家状态中会被提示的部分
+pub struct Footprint {
+    attributes: Vec<(String, i32)>,
+    items: HashMap<String, usize>,
+    money: i32,
+    location: String,
+    time: NaiveDateTime,
+}
+
+impl Footprint {
+    pub fn of(player: &Player) -> Self {
+        Self {
+            attributes: player.attributes.val.clone(),
+            items: player.items.iter().map(|(id, (_, num))| (id.clone(), *num)).collect(),
+            money: player.money,
+            location: player.game_map.clone(),
+            time: player.game_time,
+        }
+    }
+
+    /// 把现在与记下时的差别记到 player.changes
+    pub fn record(self, player: &mut Player) {
+        let mut changes = vec![];
+        for (attr, before) in self.attributes {
+            let after = player.attributes.get(&attr).copied().unwrap_or(before);
+            if after != before { changes.push(Change::Attribute { attr, delta: after - before }); }
+        }
+        let ids: BTreeSet<&String> = self.items.keys().chain(player.items.keys()).collect();
+        for id in ids {
+            let before = self.items.get(id).copied().unwrap_or(0) as i64;
+            let after = player.items.get(id).map_or(0, |(_, num)| *num) as i64;
+            if after != before {
+                changes.push(Change::Item { item: id.clone(), delta: after - before });
+                player.ledger.item(self.time, id, before as usize, after as usize);
+            }
+        }
+        if player.money != self.money { changes.push(Change::Money { delta: player.money - self.money }); }
+        if player.game_map != self.location { changes.push(Change::Location { to: player.game_map.clone() }); }
+        let minutes = (player.game_time - self.time).num_minutes();
+        if minutes > 0 { changes.push(Change::Time { minutes }); }
+        player.changes.extend(changes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn records_the_difference() {
+        let (_, _, mut player) = testing::setup("[[items]]\nname = \"tea\"");
+        player.items.insert("tea".into(), (toml::Value::Table(Default::default()), 2));
+        let before = Footprint::of(&player);
+        before.record(&mut player);
+        assert!(player.changes.is_empty()); // 没变就什么都不记
+
+        let before = Footprint::of(&player);
+        *player.attributes.get_mut("energy").unwrap() += 5;
+        *player.attributes.get_mut("health").unwrap() -= 10;
+        player.items.remove("tea");
+        player.items.insert("coffee".into(), (toml::Value::Table(Default::default()), 3));
+        player.money -= 7;
+        player.game_map = "Forest".into();
+        player.game_time += chrono::Duration::minutes(90);
+        before.record(&mut player);
+        assert_eq!(player.changes, [
+            Change::Attribute { attr: "health".into(), delta: -10 },
+            Change::Attribute { attr: "energy".into(), delta: 5 },
+            Change::Item { item: "coffee".into(), delta: 3 },
+            Change::Item { item: "tea".into(), delta: -2 },
+            Change::Money { delta: -7 },
+            Change::Location { to: "Forest".into() },
+            Change::Time { minutes: 90 },
+        ]);
+        // 物品的增减同时进账本，记在修改前的时间上
+        let items: Vec<_> = player.ledger.entries.iter().map(|e| (e.name.as_str(), e.before, e.after, e.time)).collect();
+        let start = player.game_time - chrono::Duration::minutes(90);
+        assert_eq!(items, [("coffee", 0, 3, start), ("tea", 2, 0, start)]);
+
+        // 时间往回拨不算
+        player.changes.clear();
+        let before = Footprint::of(&player);
+        player.game_time -= chrono::Duration::hours(1);
+        before.record(&mut player);
+        assert!(player.changes.is_empty());
+    }
+
+    #[test]
+    fn each_step_and_hidden() {
+        let (data, systems, mut player) = testing::setup("");
+        let modifier = |src: &str| testing::modifier(&data, src);
+        // 组里的每一步各记各的
+        modifier("group = [{ attr = 'energy', val = { Add = 1 } }, { attr = 'energy', val = { Add = 2 } }, { money = 3 }]")
+            .apply(&systems, &mut player).unwrap();
+        assert_eq!(player.changes, [
+            Change::Attribute { attr: "energy".into(), delta: 1 },
+            Change::Attribute { attr: "energy".into(), delta: 2 },
+            Change::Money { delta: 3 },
+        ]);
+        // Hidden 照常执行，但不提示
+        player.changes.clear();
+        modifier("hidden = [{ money = 3 }, { wait = { hours = 1 } }]").apply(&systems, &mut player).unwrap();
+        assert!(player.changes.is_empty());
+        assert_eq!(player.money, 6);
+    }
+}
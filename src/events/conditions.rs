@@ -1,15 +1,81 @@
+use crate::game::GameData;
 use crate::player::{Player, PlayerAttribute, PlayerItem};
-use crate::systems::Systems;
-use serde::Deserialize;
+use crate::systems::{craft_system::CraftSystem, script_system::Script, Systems};
+use chrono::{Datelike, NaiveDate, NaiveTime, Weekday};
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Clone)]
+use super::{expr::Expr, named::Definitions};
+
+/// 时间条件，各项同时满足，不写的项不限制。格式错误在读取数据时报出来。
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct TimeCondition {
-    pub start: String,     // "HH:MM"
-    pub end: String,       // "HH:MM"
-    pub days: Vec<String>, // ["Monday", "Tuesday", ...]
-    #[serde(default)]
-    pub times: Option<Vec<String>>, // ["HH:MM", ...]
+    pub start: Option<Clock>,              // "HH:MM"，晚于 end 时为跨夜，如 22:00 到 02:00
+    pub end: Option<Clock>,                // "HH:MM"，含
+    pub days: Option<Vec<Weekday>>,        // ["Mon", "Tuesday", ...]，长短写法都行
+    pub times: Option<Vec<Clock>>,         // ["HH:MM", ...]，上一轮挑事件以来跨过其中之一即满足；时间没动时按分钟比较
+    pub dates: Option<(NaiveDate, NaiveDate)>, // ["2024-09-01", "2025-01-15"]，闭区间
+    pub months: Option<Vec<u32>>,          // [9, 10]
+    pub month_days: Option<Vec<MonthDay>>, // ["12-25", "01-01"]
+    pub weeks: Option<Vec<i64>>,           // 教学周，第一周为 1，需要 calendar.term_start
+    pub every: Option<EveryDays>,          // 每隔几天
+}
+// 跨夜时，过了午夜的那一段算作前一天：周五 22:00 到 02:00 包括周六凌晨一点，
+// 星期、日期、教学周等都按前一天来判断。
+
+/// 每 days 天一次，从 from 那天算起；from 不写时从 calendar.term_start 算起
+#[derive(Debug, Deserialize, Clone)]
+pub struct EveryDays {
+    pub days: i64,
+    pub from: Option<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(try_from = "String")]
+pub struct Clock(pub NaiveTime);
+
+impl TryFrom<String> for Clock {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        NaiveTime::parse_from_str(&value, "%H:%M").map(Clock)
+            .map_err(|_| format!("时间 {value} 格式不对，应为 HH:MM"))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct MonthDay(pub u32, pub u32);
+
+impl TryFrom<String> for MonthDay {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        // 借闰年检查日期是否存在，这样 02-29 也能写
+        NaiveDate::parse_from_str(&format!("2000-{value}"), "%Y-%m-%d")
+            .map(|date| MonthDay(date.month(), date.day()))
+            .map_err(|_| format!("日期 {value} 格式不对，应为 MM-DD"))
+    }
+}
+
+impl TimeCondition {
+    pub fn validate(&self, data: &GameData) -> Vec<String> {
+        let mut errs = vec![];
+        if self.weeks.is_some() && data.calendar.term_start.is_none() {
+            errs.push("按教学周判断需要设置 calendar.term_start".to_string());
+        }
+        if let Some(every) = &self.every {
+            if every.days <= 0 { errs.push(format!("every.days 须为正数，实际为 {}", every.days)); }
+            if every.from.is_none() && data.calendar.term_start.is_none() {
+                errs.push("every 没有 from 时需要设置 calendar.term_start".to_string());
+            }
+        }
+        if let Some((from, to)) = self.dates {
+            if from > to { errs.push(format!("日期范围 {from} 晚于 {to}")); }
+        }
+        if let Some(month) = self.months.iter().flatten().find(|m| !(1..=12).contains(*m)) {
+            errs.push(format!("不存在 {month} 月"));
+        }
+        errs
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -20,6 +86,8 @@ pub struct LocationCondition {
 #[derive(Debug, Deserialize, Clone)]
 pub struct PlayerAttributeCondition {
     pub attributes: HashMap<String, AttributeCheck>,
+    #[serde(skip)]
+    pub compiled: Vec<(String, Option<usize>, AttributeCheck)>, // 属性名、序号、判断，读取数据时由 compile 填好
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -28,52 +96,352 @@ pub struct PlayerItemContition {
 }
 
 
+/// 在整个背包里找符合要求的物品，而不是按名字查某一个
+#[derive(Debug, Deserialize, Clone)]
+pub struct AnyItemCondition {
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    #[serde(default)]
+    pub tag_check: HashMap<String,ValueCheck>,
+    #[serde(default = "AnyItemCondition::default_at_least")]
+    pub at_least: usize, // 符合要求的物品总数至少为多少
+}
+
+impl AnyItemCondition {
+    fn default_at_least() -> usize { 1 }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct FreeSpaceCondition {
+    pub item: Option<String>, // 给出时同时考虑该物品的堆叠上限
+    pub at_least: usize,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MoneyCondition {
+    pub at_least: i32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RecipeCondition {
+    pub recipe: String,
+    #[serde(default)]
+    pub craftable: bool, // 不只是会，还要现在就能做
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EventSeenCondition {
+    pub event: String,
+    pub segment: Option<String>, // 给出时数的是该段落走过几次
+    #[serde(default = "EventSeenCondition::default_at_least")]
+    pub at_least: usize,
+    pub at_most: Option<usize>,  // 写 at_most = 0 即“从没经历过”
+}
+
+impl EventSeenCondition {
+    fn default_at_least() -> usize { 1 }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OptionChosenCondition {
+    pub event: String,
+    pub segment: String,
+    pub option: usize, // 选项序号，从 0 开始
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DaysSinceCondition {
+    pub event: String,
+    pub at_least: i64, // 按日历日算。从没经历过的事件视为满足
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScriptCondition {
+    pub script: Script, // 须返回 bool
+}
+
+/// 对属性的判断，各项同时满足。比较对象可以是常数、另一个属性或某物品的数量，
+/// 例如 energy = { at_least = { attr = "health" } }
 #[derive(Debug, Deserialize, Clone)]
 pub struct AttributeCheck {
-    pub greater_than: Option<i32>,
-    pub less_than: Option<i32>,
+    pub greater_than: Option<Operand>,
+    pub less_than: Option<Operand>,
+    pub at_least: Option<Operand>,
+    pub at_most: Option<Operand>,
+    pub equals: Option<Operand>,
+    pub not_equals: Option<Operand>,
+    pub between: Option<(Operand, Operand)>, // 闭区间
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Operand {
+    Const(i32),
+    Attr { attr: String, #[serde(skip)] index: Option<usize> }, // 实际生效的属性值，含装备加成
+    ItemCount { item: String },  // 背包里该物品的数量
+}
+
+impl Operand {
+    pub fn value(&self, systems: &Systems, player: &Player) -> Result<i32, String> {
+        match self {
+            Operand::Const(v) => Ok(*v),
+            Operand::Attr { attr, index } => player.attribute_at(systems, attr, *index)
+                .ok_or_else(|| format!("未知属性 {attr}")),
+            Operand::ItemCount { item } => Ok(player.items.get(item).map_or(0, |(_, num)| *num as i32)),
+        }
+    }
+
+    fn describe(&self, value: i32, systems: &Systems) -> String {
+        match self {
+            Operand::Const(_) => value.to_string(),
+            Operand::Attr { attr, .. } => format!("{attr}（{value}）"),
+            Operand::ItemCount { item } => format!("{}的数量（{value}）", systems.item.displayed_name(item)),
+        }
+    }
+
+    fn validate(&self, data: &GameData) -> Option<String> {
+        match self {
+            Operand::Attr { attr, .. } if !data.player.iter().any(|a| &a.name == attr) => Some(format!("未知属性 {attr}")),
+            Operand::ItemCount { item } if !data.has_item(item) => Some(format!("未知物品 {item}")),
+            _ => None,
+        }
+    }
+}
+
+/// 比较对象、比较方法、说明用的词
+type Bound<'a> = (&'a Option<Operand>, fn(&i32, &i32) -> bool, &'static str);
+
+impl AttributeCheck {
+    fn bounds(&self) -> [Bound<'_>; 6] {
+        [
+            (&self.greater_than, i32::gt, "大于"),
+            (&self.less_than, i32::lt, "小于"),
+            (&self.at_least, i32::ge, "至少"),
+            (&self.at_most, i32::le, "至多"),
+            (&self.equals, i32::eq, "等于"),
+            (&self.not_equals, i32::ne, "不等于"),
+        ]
+    }
+
+    /// 判定走得很勤，这里不拼任何说明文字
+    pub fn check(&self, value: i32, systems: &Systems, player: &Player) -> Result<bool, String> {
+        for (op, cmp, _) in self.bounds() {
+            let Some(op) = op else { continue; };
+            if !cmp(&value, &op.value(systems, player)?) { return Ok(false); }
+        }
+        if let Some((lo, hi)) = &self.between {
+            if value < lo.value(systems, player)? || value > hi.value(systems, player)? { return Ok(false); }
+        }
+        Ok(true)
+    }
+
+    /// 第一条没满足的要求，如“至少 50”
+    pub fn unmet(&self, value: i32, systems: &Systems, player: &Player) -> Result<Option<String>, String> {
+        for (op, cmp, word) in self.bounds() {
+            let Some(op) = op else { continue; };
+            let target = op.value(systems, player)?;
+            if !cmp(&value, &target) { return Ok(Some(format!("{word} {}", op.describe(target, systems)))); }
+        }
+        if let Some((lo, hi)) = &self.between {
+            let (l, h) = (lo.value(systems, player)?, hi.value(systems, player)?);
+            if value < l || value > h {
+                return Ok(Some(format!("在 {} 到 {} 之间", lo.describe(l, systems), hi.describe(h, systems))));
+            }
+        }
+        Ok(None)
+    }
+
+    fn operands(&self) -> impl Iterator<Item = &Operand> {
+        [&self.greater_than, &self.less_than, &self.at_least, &self.at_most, &self.equals, &self.not_equals]
+            .into_iter()
+            .flatten()
+            .chain(self.between.iter().flat_map(|(lo, hi)| [lo, hi]))
+    }
+
+    fn compile(&mut self, attrs: &[String]) {
+        let operands = [&mut self.greater_than, &mut self.less_than, &mut self.at_least,
+            &mut self.at_most, &mut self.equals, &mut self.not_equals];
+        for op in operands.into_iter().flatten().chain(self.between.iter_mut().flat_map(|(lo, hi)| [lo, hi])) {
+            if let Operand::Attr { attr, index } = op {
+                *index = attrs.iter().position(|a| a == attr);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ItemCheck {
     pub expect_existence: Option<bool>,
-    pub expect_tags: Option<String>,
+    pub expect_tags: Option<String>, // 定义中的 tags，或者属性表中的键
     pub more_than: Option<usize>,
-    pub less_than: Option<usize>
-    // pub tag_check: HashMap<String,ValueCheck>,
+    pub less_than: Option<usize>,
+    #[serde(default)]
+    pub tag_check: HashMap<String,ValueCheck>, // 属性路径 -> 判断，路径用点号深入嵌套表，如 "book.edition"
+}
+
+/// 对物品属性值的判断。数值比较不区分整数与浮点数
+#[derive(Debug, Deserialize, Clone)]
+pub enum ValueCheck {
+    Equals(toml::Value),
+    NotEquals(toml::Value),
+    GreaterThan(f64),
+    LessThan(f64),
+    AtLeast(f64),
+    AtMost(f64),
+    Between(f64, f64),  // 闭区间
+    Contains(String),   // 字符串含有该子串，或数组含有该字符串
+    Exists(bool),
 }
 
-// #[derive(Debug, Deserialize, Clone)]
-// pub enum ValueCheck {
-//     Equals(toml::Value),
-// }
+impl ValueCheck {
+    pub fn check(&self, value: Option<&toml::Value>) -> bool {
+        let num = value.and_then(|v| v.as_float().or(v.as_integer().map(|i| i as f64)));
+        match self {
+            ValueCheck::Exists(exists) => value.is_some() == *exists,
+            ValueCheck::Equals(expect) => value.is_some_and(|v| Self::equals(v, expect)),
+            ValueCheck::NotEquals(expect) => !value.is_some_and(|v| Self::equals(v, expect)),
+            ValueCheck::GreaterThan(x) => num.is_some_and(|n| n > *x),
+            ValueCheck::LessThan(x) => num.is_some_and(|n| n < *x),
+            ValueCheck::AtLeast(x) => num.is_some_and(|n| n >= *x),
+            ValueCheck::AtMost(x) => num.is_some_and(|n| n <= *x),
+            ValueCheck::Between(lo, hi) => num.is_some_and(|n| *lo <= n && n <= *hi),
+            ValueCheck::Contains(sub) => match value {
+                Some(toml::Value::String(s)) => s.contains(sub.as_str()),
+                Some(toml::Value::Array(arr)) => arr.iter().any(|v| v.as_str() == Some(sub.as_str())),
+                _ => false,
+            },
+        }
+    }
+
+    fn equals(value: &toml::Value, expect: &toml::Value) -> bool {
+        match (value, expect) {
+            (toml::Value::Integer(a), toml::Value::Float(b))
+                | (toml::Value::Float(b), toml::Value::Integer(a)) => *a as f64 == *b,
+            _ => value == expect,
+        }
+    }
+
+    /// 按点号分隔的路径在属性表中取值
+    pub fn lookup<'a>(props: &'a toml::Table, path: &str) -> Option<&'a toml::Value> {
+        let mut keys = path.split('.');
+        let mut value = props.get(keys.next()?)?;
+        for key in keys {
+            value = value.as_table()?.get(key)?;
+        }
+        Some(value)
+    }
+
+    pub fn check_all(checks: &HashMap<String,ValueCheck>, props: &toml::Table) -> bool {
+        checks.iter().all(|(path, check)| check.check(Self::lookup(props, path)))
+    }
+}
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ConditionGroup {
     pub conds: Vec<Condition>
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct NotCondition {
+    pub cond: Box<Condition>,
+}
+
+/// 引用 GameData.conditions 里的定义，读取数据时展开，见 named.rs
+#[derive(Debug, Deserialize, Clone)]
+pub struct NamedCondition {
+    pub name: String,
+}
+
+// 除了 { type = "...", ... } 的表，也可以直接写一个表达式字符串，见 expr.rs。
+// remote = "Self" 让派生出的实现成为固有函数，由下面手写的 Deserialize 先分辨是不是字符串。
 #[derive(Debug, Deserialize, Clone, Default)]
-#[serde(tag = "type")]
+#[serde(tag = "type", remote = "Self")]
 pub enum Condition {
     Time(TimeCondition),
     Location(LocationCondition),
     PlayerAttribute(PlayerAttributeCondition),
     PlayerItem(PlayerItemContition),
+    AnyItem(AnyItemCondition),
+    FreeSpace(FreeSpaceCondition),
+    Money(MoneyCondition),
+    Recipe(RecipeCondition),
 
     RandomCondition(f64),
+    EventSeen(EventSeenCondition),
+    OptionChosen(OptionChosenCondition),
+    DaysSince(DaysSinceCondition),
+    Script(ScriptCondition),
+    #[serde(skip)]
+    Expr(Expr),
     // 可以扩展更多条件类型
 
     // 逻辑条件
     And(ConditionGroup),
     Or(ConditionGroup),
     Xor(ConditionGroup),
+    Not(NotCondition),
+    Named(NamedCondition),
 
     False,
     #[default]
     True,
 }
 
+/// 条件为什么没满足，用来告诉玩家选项为什么点不了
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unmet {
+    Reason(String),
+    All(Vec<Unmet>), // 这些都得满足
+    Any(Vec<Unmet>), // 满足其一即可
+}
+
+impl Unmet {
+    fn all(mut list: Vec<Unmet>) -> Option<Unmet> {
+        match list.len() {
+            0 => None,
+            1 => list.pop(),
+            _ => Some(Unmet::All(list)),
+        }
+    }
+
+    fn render(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        match self {
+            Unmet::Reason(reason) => lines.push(format!("{indent}{reason}")),
+            Unmet::All(list) => list.iter().for_each(|unmet| unmet.render(depth, lines)),
+            Unmet::Any(list) => {
+                lines.push(format!("{indent}以下满足其一："));
+                for unmet in list {
+                    if let Unmet::All(_) = unmet { // 不单独列出来就分不清哪些是一组的了
+                        lines.push(format!("{indent}  以下全部满足："));
+                        unmet.render(depth + 2, lines);
+                    } else {
+                        unmet.render(depth + 1, lines);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Unmet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut lines = vec![];
+        self.render(0, &mut lines);
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        match toml::Value::deserialize(deserializer)? {
+            toml::Value::String(src) => Expr::parse(&src).map(Condition::Expr).map_err(D::Error::custom),
+            value => Condition::deserialize(value).map_err(D::Error::custom),
+        }
+    }
+}
+
 pub type PlayerCond<'a> = (&'a PlayerItem, &'a PlayerAttribute);
 
 impl<'a> Into<PlayerCond<'a>> for &'a Player {
@@ -100,39 +468,62 @@ impl Condition {
                 .locations
                 .contains(&player.game_map),
             Condition::PlayerAttribute(cond) => {
-                for (attr, check) in &cond.attributes {
-                    let value = *player.attributes.get(attr).unwrap();
-                    if check.greater_than.is_some_and(|v|v>=value)
-                        || check.less_than.is_some_and(|v|v<=value) { 
-                            return false; 
-                    }
+                let met = |attr: &String, index: Option<usize>, check: &AttributeCheck| player
+                    .attribute_at(systems, attr, index)
+                    .ok_or_else(|| format!("未知属性 {attr}"))
+                    .and_then(|value| check.check(value, systems, player))
+                    .unwrap_or_else(|e| { eprintln!("ERROR: {e}"); false });
+                if cond.compiled.is_empty() { // 没编译过，比如运行时临时拼出来的条件
+                    cond.attributes.iter().all(|(attr, check)| met(attr, None, check))
+                } else {
+                    cond.compiled.iter().all(|(attr, index, check)| met(attr, *index, check))
                 }
-                true
             }
             Condition::PlayerItem(cond) => {
-                for (item,check) in &cond.items {
-                    let item = player.items.get(item);
+                for (id,check) in &cond.items {
+                    let item = player.items.get(id);
                     if let Some(exsists) = check.expect_existence {
                         if item.is_some() != exsists { return false; }
                     }
-                    let Some((item,num)) = item else { return false; };
+                    let Some((item,num)) = item else {
+                        if check.expect_existence == Some(false) { continue; }
+                        return false;
+                    };
                     if check.more_than.is_some_and(|v|v>=*num)
                         || check.less_than.is_some_and(|v|v<=*num) { 
                             return false; 
                     }
-                    if !item.is_table() {
-                        if check.expect_tags.is_none() { continue; }
-                        else { return false; }
+                    if let Some(tag) = &check.expect_tags {
+                        if !systems.item.has_tag(id, item, tag) { return false; }
                     }
-                    let toml::Value::Table(item) = item else { panic!("never") };
-                    if check.expect_tags.is_some()
-                        &&!item.contains_key(check.expect_tags.as_ref().unwrap()) {
+                    if !check.tag_check.is_empty()
+                        && !ValueCheck::check_all(&check.tag_check, &systems.item.properties(id, item)) {
                             return false;
-                        }
-                    
+                    }
                 }
                 true
             },
+            Condition::AnyItem(cond) => {
+                let count: usize = player.items.iter()
+                    .filter(|(id, (item, _))| {
+                        cond.tag.as_ref().is_none_or(|tag| systems.item.has_tag(id, item, tag))
+                        && cond.category.as_ref().is_none_or(|category|
+                            systems.item.items.get(*id).is_some_and(|def| &def.category == category))
+                        && ValueCheck::check_all(&cond.tag_check, &systems.item.properties(id, item))
+                    })
+                    .map(|(_, (_, num))| num)
+                    .sum();
+                count >= cond.at_least
+            },
+            Condition::FreeSpace(cond) => systems.item
+                .free_space(&player.items, cond.item.as_deref())
+                .is_none_or(|space| space >= cond.at_least),
+            Condition::Money(cond) => player.money >= cond.at_least,
+            Condition::Recipe(cond) => if cond.craftable {
+                CraftSystem::can_craft(systems, player, &cond.recipe).is_ok()
+            } else {
+                systems.craft.is_known(player, &cond.recipe)
+            },
             Condition::And(vec) => {
                 vec.conds.iter().all(|cond| cond.is_met(systems,player))
             },
@@ -142,11 +533,191 @@ impl Condition {
             Condition::Xor(vec) => {
                 vec.conds.iter().fold(false, |fold,cond| fold^cond.is_met(systems,player))
             },
+            Condition::Not(not) => !not.cond.is_met(systems, player),
+            Condition::Named(named) => {
+                eprintln!("ERROR: 具名条件 {} 未展开", named.name);
+                false
+            },
             Condition::RandomCondition(prop) => {
-                rand::random::<f64>()  < *prop
+                player.rng.next_f64() < *prop
             },
+            Condition::EventSeen(cond) => {
+                let record = player.history.get(&cond.event);
+                let seen = match &cond.segment {
+                    Some(seg) => record.map_or(0, |r| r.segment_visits(seg)),
+                    None => record.map_or(0, |r| r.visits),
+                };
+                seen >= cond.at_least && cond.at_most.is_none_or(|most| seen <= most)
+            },
+            Condition::OptionChosen(cond) => player.history.get(&cond.event)
+                .is_some_and(|r| r.chosen(&cond.segment, cond.option) > 0),
+            Condition::DaysSince(cond) => player.history.get(&cond.event)
+                .and_then(|r| r.last)
+                .is_none_or(|last| (player.game_time.date() - last.date()).num_days() >= cond.at_least),
+            Condition::Script(cond) => systems.script.condition(&cond.script, player)
+                .unwrap_or_else(|e| { eprintln!("ERROR: {e}"); false }),
+            Condition::Expr(expr) => expr.eval(systems, player)
+                .map(|val| val.truthy())
+                .unwrap_or_else(|e| { eprintln!("ERROR: {e}"); false }),
             Condition::False => false,
             Condition::True => true,
         }
     }
+
+    /// 不满足时说明原因，满足时为 None。And、Or、属性、物品、时间等给出具体原因，其余的只说不满足
+    pub fn explain(&self, systems: &Systems, player: &Player) -> Option<Unmet> {
+        match self {
+            Condition::Time(cond) => systems.time.check(player, cond).err().map(Unmet::Reason),
+            Condition::Location(cond) if !cond.locations.contains(&player.game_map) => {
+                let names: Vec<&str> = cond.locations.iter()
+                    .map(|loc| systems.map.maps.get(loc)
+                        .and_then(|map| map.displayed_name.as_deref())
+                        .unwrap_or(loc))
+                    .collect();
+                Some(Unmet::Reason(format!("只能在 {}", names.join("、"))))
+            },
+            Condition::PlayerAttribute(cond) => {
+                let mut attrs: Vec<_> = cond.attributes.iter().collect();
+                attrs.sort_by_key(|(attr, _)| *attr);
+                Unmet::all(attrs.into_iter().filter_map(|(attr, check)| {
+                    let unmet = player.attribute(systems, attr)
+                        .ok_or(format!("未知属性 {attr}"))
+                        .and_then(|value| Ok(check.unmet(value, systems, player)?
+                            .map(|need| format!("{attr} 为 {value}，需要{need}"))));
+                    match unmet {
+                        Ok(unmet) => unmet.map(Unmet::Reason),
+                        Err(e) => Some(Unmet::Reason(e)),
+                    }
+                }).collect())
+            },
+            Condition::PlayerItem(cond) => {
+                let mut items: Vec<_> = cond.items.iter().collect();
+                items.sort_by_key(|(id, _)| *id);
+                Unmet::all(items.into_iter().filter_map(|(id, check)| {
+                    let name = systems.item.displayed_name(id);
+                    let unmet = match player.items.get(id) {
+                        None if check.expect_existence == Some(false) => None,
+                        None => Some(format!("需要{name}")),
+                        Some(_) if check.expect_existence == Some(false) => Some(format!("不能带着{name}")),
+                        Some((_, num)) if check.more_than.is_some_and(|v| v >= *num) =>
+                            Some(format!("{name}需要多于 {} 个（现有 {num} 个）", check.more_than.unwrap())),
+                        Some((_, num)) if check.less_than.is_some_and(|v| v <= *num) =>
+                            Some(format!("{name}需要少于 {} 个（现有 {num} 个）", check.less_than.unwrap())),
+                        Some(_) => {
+                            let single = Condition::PlayerItem(PlayerItemContition {
+                                items: HashMap::from([(id.clone(), check.clone())])
+                            });
+                            (!single.is_met(systems, player)).then(|| format!("{name}不符合要求"))
+                        },
+                    };
+                    unmet.map(Unmet::Reason)
+                }).collect())
+            },
+            Condition::Money(cond) if player.money < cond.at_least =>
+                Some(Unmet::Reason(format!("需要 {} {}", cond.at_least, systems.shop.currency.name))),
+            Condition::And(group) => Unmet::all(group.conds.iter()
+                .filter_map(|cond| cond.explain(systems, player))
+                .collect()),
+            Condition::Or(group) => {
+                let mut list = vec![];
+                for cond in &group.conds {
+                    list.push(cond.explain(systems, player)?);
+                }
+                Some(Unmet::Any(list))
+            },
+            Condition::Location(_) | Condition::Money(_) => None,
+            _ => (!self.is_met(systems, player)).then(|| Unmet::Reason("条件不满足".to_string())),
+        }
+    }
+
+    /// 读取数据时的检查，返回发现的问题
+    pub fn validate(&self, data: &GameData) -> Vec<String> {
+        match self {
+            Condition::Time(cond) => cond.validate(data),
+            Condition::PlayerAttribute(cond) => cond.attributes.iter()
+                .flat_map(|(attr, check)| {
+                    let known = data.player.iter().any(|a| &a.name == attr);
+                    (!known).then(|| format!("未知属性 {attr}")).into_iter()
+                        .chain(check.operands().filter_map(|op| op.validate(data)))
+                })
+                .collect(),
+            Condition::PlayerItem(cond) => cond.items.keys()
+                .filter(|id| !data.has_item(id))
+                .map(|id| format!("未知物品 {id}"))
+                .collect(),
+            Condition::FreeSpace(FreeSpaceCondition { item: Some(id), .. }) if !data.has_item(id) =>
+                vec![format!("未知物品 {id}")],
+            Condition::Recipe(cond) if !data.has_recipe(&cond.recipe) =>
+                vec![format!("未知配方 {}", cond.recipe)],
+            Condition::EventSeen(EventSeenCondition { event, segment, .. }) =>
+                Self::validate_event(data, event, segment.as_deref(), None),
+            Condition::OptionChosen(cond) =>
+                Self::validate_event(data, &cond.event, Some(&cond.segment), Some(cond.option)),
+            Condition::DaysSince(cond) => Self::validate_event(data, &cond.event, None, None),
+            Condition::Expr(expr) => expr.validate(data),
+            Condition::And(vec) | Condition::Or(vec) | Condition::Xor(vec) => vec.conds.iter()
+                .flat_map(|cond| cond.validate(data))
+                .collect(),
+            Condition::Not(not) => not.cond.validate(data),
+            Condition::Named(named) => vec![format!("具名条件 {} 未展开", named.name)],
+            _ => vec![],
+        }
+    }
+
+    /// 读取数据时把属性名换成属性序号（attrs 为属性定义的顺序），判定时就不用按名字找了
+    pub fn compile(&mut self, attrs: &[String]) {
+        match self {
+            Condition::PlayerAttribute(cond) => {
+                let mut compiled: Vec<_> = cond.attributes.iter()
+                    .map(|(attr, check)| {
+                        let mut check = check.clone();
+                        check.compile(attrs);
+                        (attr.clone(), attrs.iter().position(|a| a == attr), check)
+                    })
+                    .collect();
+                compiled.sort_by_key(|(_, index, _)| *index);
+                cond.compiled = compiled;
+            },
+            Condition::And(vec) | Condition::Or(vec) | Condition::Xor(vec) =>
+                vec.conds.iter_mut().for_each(|cond| cond.compile(attrs)),
+            Condition::Not(not) => not.cond.compile(attrs),
+            Condition::Expr(expr) => expr.compile(attrs),
+            _ => (),
+        }
+    }
+
+    /// 其中所有的脚本，读取数据时编译
+    pub fn scripts(&mut self) -> Vec<&mut Script> {
+        match self {
+            Condition::Script(cond) => vec![&mut cond.script],
+            Condition::And(vec) | Condition::Or(vec) | Condition::Xor(vec) =>
+                vec.conds.iter_mut().flat_map(|cond| cond.scripts()).collect(),
+            Condition::Not(not) => not.cond.scripts(),
+            _ => vec![],
+        }
+    }
+
+    /// 把具名条件的引用换成定义的内容
+    pub fn resolve(&mut self, defs: &mut Definitions) -> Result<(), String> {
+        match self {
+            Condition::Named(named) => *self = defs.condition(&named.name)?,
+            Condition::And(vec) | Condition::Or(vec) | Condition::Xor(vec) =>
+                for cond in &mut vec.conds { cond.resolve(defs)?; },
+            Condition::Not(not) => not.cond.resolve(defs)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn validate_event(data: &GameData, event: &str, segment: Option<&str>, option: Option<usize>) -> Vec<String> {
+        let Some(evt) = data.events.iter().find(|evt| evt.name == event)
+            else { return vec![format!("未知事件 {event}")]; };
+        let Some(segment) = segment else { return vec![]; };
+        let Some(seg) = evt.segments.iter().find(|seg| seg.name == segment)
+            else { return vec![format!("事件 {event} 没有段落 {segment}")]; };
+        match option {
+            Some(i) if i >= seg.options.len() => vec![format!("事件 {event} 段落 {segment} 没有第 {i} 个选项")],
+            _ => vec![],
+        }
+    }
 }
@@ -1,11 +1,15 @@
-use crate::frontend::Frontend;
+use crate::frontend::{FromFrontend, Frontend};
 use crate::game;
 use crate::player::Player;
-use crate::systems::Systems;
+use crate::systems::{craft_system::CraftSystem, item_system::ItemSystem, script_system::Script, shop_system::ShopSystem, Systems};
 use serde::Deserialize;
 
 use super::conditions::Condition;
 use super::modifier::Modifier;
+use super::changes::Footprint;
+use super::template;
+use crate::ledger::Source;
+use super::schedule::Delay;
 use super::triggers::Trigger;
 use anyhow::Result;
 use std::cmp::Ordering;
@@ -16,6 +20,7 @@ use std::collections::HashMap;
 pub struct EventOption {
     pub text: String,                  // 描述
     pub condition: Option<Condition>,  // 选项的条件——是不是下面那个hide or not得放在这里？
+    pub disabled_reason: Option<String>, // 选项不可用时给玩家看的说明。不写则按条件自动生成
 
     pub jump_to_event: Option<String>, // 选项下一个event。总是结束当前事件
     pub jump_to: Option<String>,       // 选项下一个segment。如果为空，结束事件
@@ -24,6 +29,9 @@ pub struct EventOption {
 
     #[serde(default)]
     pub modifier: Modifier,             // 默认为不修改
+    #[serde(default)]
+    pub time: Delay,                    // 选了之后过去多久，如 { minutes = 30 }
+    pub fallback: Option<String>,       // 修改出错（已整体撤销）时跳到的段落。不写则留在本段落重选
 
     // #[serde(default)]
     // pub modifications: Option<HashMap<String, i32>>, // 属性修正
@@ -46,6 +54,7 @@ pub struct EventSegment {
     pub name: String,
     #[serde(default)]
     pub text: String,
+    pub text_script: Option<Script>, // 给出时用脚本的返回值作为文本，出错时退回 text
     #[serde(default)]
     pub silent: bool,
     #[serde(default)]
@@ -132,7 +141,9 @@ impl EventSystem {
         //         .unwrap_or(None);
         // }
 
-        let Some((mut event_name, mut segment_name)) = player.cur_evt_seg.clone() else { return Ok(None);};
+        let Some((event_name, segment_name)) = player.cur_evt_seg.clone() else {
+            return self.idle(player, systems, frontend);
+        };
         // 获取当前事件数据
         let Some(event) = self.events.get(&event_name) else { return Ok(None);};
         
@@ -144,32 +155,69 @@ impl EventSystem {
             .and_then(|seg_name| event.segments.iter().find(|seg| seg.name.eq(seg_name)))
             .or(event.segments.first())
         else { return Ok(None);};
-        frontend.cache.display_text(&segment.text);
+        let text = player.rng.preview(|| match &segment.text_script {
+            Some(script) => systems.script.text(script, player)
+                .unwrap_or_else(|e| { eprintln!("ERROR: {e}"); template::render(&segment.text, systems, player) }),
+            None => template::render(&segment.text, systems, player),
+        });
+        frontend.cache.display_text(&text);
 
-        if segment.options.is_empty() { return Ok(None); }
+        if segment.options.is_empty() {
+            let record = player.history.entry(event_name).or_default();
+            record.pass_segment(&segment.name, None);
+            record.leave(player.game_time);
+            return Ok(None);
+        }
 
         // 选项与判定
 
-        let options: Vec<(String,bool)> = segment
-            .options.iter().map(|opt| (
-                opt.text.clone(),             // 文本
-                !opt.condition.as_ref() // 与“没有条件或条件成立”
-                    .is_some_and(|c| !c.is_met(systems,&player))
-            )).collect();
-
-        let selected_option = if segment.silent {
+        let selected = if segment.silent {
             // 如果为无声事件，则自动选择，然后进入下一阶段。有意义吗？我不知道，就这么放着吧。如果无声事件寄了，直接err吧抬走不送
-            options.iter().enumerate().filter(|p| p.1.1 )
-                .next().map(|(i,_)|&segment.options[i]).unwrap()
+            Self::options(segment, systems, player).iter().enumerate().filter(|p| p.1.1 )
+                .next().map(|(i,_)| i).unwrap()
         } else { 
-            // 前端保证如此；相信前端。
-            &segment.options[{
-                frontend.display_options(&options,segment.hide_disabled_options)?
-            }]
+            // 等待选择时玩家可以使用物品。用完之后条件可能变了，所以每次都重新判定
+            loop {
+                let options = Self::options(segment, systems, player);
+                frontend.display_shops(player, systems);
+                frontend.display_recipes(player, systems);
+                match frontend.display_options(&options,segment.hide_disabled_options)? {
+                    // 前端保证如此；相信前端。
+                    FromFrontend::Choice(id) => break id,
+                    input => {
+                        let depth = player.evt_stack.len();
+                        if let Some(evt) = Self::act(input, player, systems, frontend) {
+                            // 物品引发的事件插队执行，结束后回到当前段落
+                            player.evt_stack.insert(depth, (event_name, segment_name));
+                            return Ok(Some(evt));
+                        }
+                    }
+                }
+            }
+        };
+        let selected_option = &segment.options[selected];
+        player.ledger.source = Source {
+            event: Some(event_name.clone()),
+            segment: Some(segment.name.clone()),
+            option: Some(selected),
+            action: None,
         };
 
-        // 应用属性修改
-        selected_option.modifier.modify(systems,player)?;
+        // 应用属性修改。出错时已经恢复原样，提示一下，不算选过这个选项
+        let depth = player.evt_stack.len();
+        if let Err(e) = selected_option.modifier.apply(systems, player) {
+            frontend.cache.display_error(&e.to_string());
+            let segment = selected_option.fallback.clone().or(segment_name);
+            return Ok(Some((event_name, segment)));
+        }
+        let before = Footprint::of(player);
+        systems.time.advance(player, selected_option.time.duration());
+        before.record(player);
+        frontend.display_changes(player, systems);
+        frontend.display_ledger(player);
+        if selected_option.modifier.touches_items() {
+            frontend.display_inventory(player, systems);
+        }
         // if let Some(ref modifications) = selected_option.modifications {
         //     for (attr, value) in modifications {
         //         // player.modify_attribute(attr, *value);
@@ -207,14 +255,19 @@ impl EventSystem {
             }
         }
 
-        match (&selected_option.jump_to_event,&selected_option.jump_to) {
-            (None,None) => {player.cur_evt_seg = None;}
-            (Some(evt),seg) => { event_name = evt.clone(); segment_name = seg.clone();}
-            (None,Some(jump_to)) => {segment_name = Some(jump_to.clone());}
+        let next = match (&selected_option.jump_to_event,&selected_option.jump_to) {
+            (None,None) => None,
+            (Some(evt),seg) => Some((evt.clone(), seg.clone())),
+            (None,Some(jump_to)) => Some((event_name.clone(), Some(jump_to.clone()))),
+        };
+
+        // 记录经历。跳到别的事件或者结束，就算离开了这个事件
+        let record = player.history.entry(event_name.clone()).or_default();
+        record.pass_segment(&segment.name, Some(selected));
+        if next.as_ref().is_none_or(|(evt, _)| *evt != event_name) {
+            record.leave(player.game_time);
         }
 
-        // 跳转到指定段落
-        
 
         if let Some(trigger) = selected_option.trigger.clone() {
             for tr in trigger {
@@ -223,7 +276,90 @@ impl EventSystem {
         }
 
         // player.stuck_in_event = player.cur_evt_seg.is_some() && able_to_stuck;
-        Ok(Some((event_name,segment_name)))
+        Ok(Self::take_fired(player, depth, next))
+    }
+
+    /// 修改器立即触发的事件（见 Modifier::Trigger）压在栈上 depth 之后。
+    /// 最先触发的马上开始，其余的按触发顺序接着来，最后才回到 resume（原本接下来要去的地方）
+    fn take_fired(player: &mut Player, depth: usize, resume: Option<(String, Option<String>)>) -> Option<(String, Option<String>)> {
+        let mut fired = player.evt_stack.split_off(depth);
+        if fired.is_empty() { return resume; }
+        let first = fired.remove(0);
+        player.evt_stack.extend(resume);
+        player.evt_stack.extend(fired.into_iter().rev());
+        Some(first)
+    }
+
+    /// 文本、是否可选、不可选的原因。用完物品还会再判定一次，所以不动随机源
+    fn options(segment: &EventSegment, systems: &Systems, player: &Player) -> Vec<(String,bool,Option<String>)> {
+        player.rng.preview(|| segment
+            .options.iter().map(|opt| {
+                let cost = opt.modifier.cost(systems);
+                let unmet = match opt.condition.as_ref().and_then(|c| c.explain(systems, player)) {
+                    Some(unmet) => Some(unmet.to_string()),
+                    // 要花钱的选项自动要求买得起
+                    None if player.money < cost => Some(format!("需要 {cost} {}", systems.shop.currency.name)),
+                    None => None,
+                };
+                let reason = unmet.map(|unmet| opt.disabled_reason.as_ref()
+                    .map_or(unmet, |reason| template::render(reason, systems, player)));
+                (template::render(&opt.text, systems, player), reason.is_none(), reason)
+            }).collect())
+    }
+
+    /// 处理选项以外的操作：使用物品、买卖、合成。返回需要插队执行的事件（和段落）。
+    /// 这些操作失败（钱不够之类）只是提示一下，不影响当前事件。
+    fn act(
+        input: FromFrontend,
+        player: &mut Player,
+        systems: &Systems,
+        frontend: &mut Frontend,
+    ) -> Option<(String, Option<String>)> {
+        let action = match &input {
+            FromFrontend::UseItem(item) => format!("使用 {item}"),
+            FromFrontend::Buy { shop, item, count } => format!("在 {shop} 买 {item} ×{count}"),
+            FromFrontend::Sell { shop, item, count } => format!("在 {shop} 卖 {item} ×{count}"),
+            FromFrontend::Craft(recipe) => format!("合成 {recipe}"),
+            _ => String::new(),
+        };
+        player.ledger.source = Source::action(&player.cur_evt_seg, action);
+        // 使用物品走修改器，修改器自己会记下变化；买卖、合成直接改背包，在这里记
+        let before = (!matches!(input, FromFrontend::UseItem(_))).then(|| Footprint::of(player));
+        let depth = player.evt_stack.len();
+        let ret = match input {
+            FromFrontend::UseItem(item) => ItemSystem::use_item(systems, player, &item),
+            FromFrontend::Buy { shop, item, count } => {
+                if ShopSystem::open_shops(systems, player).any(|s| s.name == shop) {
+                    ShopSystem::buy(systems, player, &shop, &item, count).map(|_| None)
+                } else { Err(anyhow::anyhow!("商店没有开门")) }
+            },
+            FromFrontend::Sell { shop, item, count } => {
+                if ShopSystem::open_shops(systems, player).any(|s| s.name == shop) {
+                    ShopSystem::sell(systems, player, &shop, &item, count).map(|_| None)
+                } else { Err(anyhow::anyhow!("商店没有开门")) }
+            },
+            FromFrontend::Craft(recipe) => CraftSystem::craft(systems, player, &recipe).map(|_| None),
+            _ => Ok(None),
+        };
+        if let Some(before) = before { before.record(player); }
+        frontend.display_inventory(player, systems);
+        frontend.display_changes(player, systems);
+        frontend.display_ledger(player);
+        let evt = ret.unwrap_or_else(|e| { frontend.cache.display_error(&e.to_string()); None });
+        Self::take_fired(player, depth, evt.map(|evt| (evt, None)))
+    }
+
+    /// 没有事件在进行时，只能等玩家使用物品、买东西、合成（或者调试）。
+    fn idle(
+        &self,
+        player: &mut Player,
+        systems: &Systems,
+        frontend: &mut Frontend,
+    ) -> Result<Option<(String, Option<String>)>, game::GameErr> {
+        frontend.display_shops(player, systems);
+        frontend.display_recipes(player, systems);
+        let input = frontend.display_options(&[], false)?;
+        Ok(Self::act(input, player, systems, frontend))
     }
 
     // fn should_trigger_event(&self, _event: &EventData, _player: &Player) -> bool {
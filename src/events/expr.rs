@@ -0,0 +1,633 @@
+// 表达式。可以用在任何接受 Condition 的地方，也可以作为 ValModifier 中的数值，例如
+// "health > 50 && has('student_card') && time.weekday in [Mon, Wed]"
+// 读取数据时解析（语法错误当场报出来），运行时对着 Systems 与 Player 求值。
+// 字符串用单引号或双引号括起来，里面可以写 \' \" \\ \n 转义。
+
+use chrono::{Datelike, Timelike};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::{game::GameData, player::Player, systems::Systems};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    List(Vec<Expr>),
+    Var(String), // 可以带点号，如 time.weekday、attr.health
+    Attr(String, usize), // 编译时从 Var 换来的属性：名字（报错用）与序号，见 compile
+    Call(String, Vec<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp { Not, Neg }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp { Or, And, Eq, Ne, Lt, Le, Gt, Ge, In, Add, Sub, Mul, Div, Rem }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Val {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    List(Vec<Val>),
+}
+
+impl Val {
+    pub fn truthy(&self) -> bool {
+        match self {
+            Val::Bool(b) => *b,
+            Val::Num(n) => *n != 0.,
+            Val::Str(s) => !s.is_empty(),
+            Val::List(l) => !l.is_empty(),
+        }
+    }
+
+    fn num(&self) -> Result<f64, String> {
+        match self {
+            Val::Num(n) => Ok(*n),
+            Val::Bool(b) => Ok(*b as i32 as f64),
+            other => Err(format!("{other} 不是数")),
+        }
+    }
+}
+
+impl fmt::Display for Val {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Val::Num(n) => write!(f, "{n}"),
+            Val::Str(s) => write!(f, "'{s}'"),
+            Val::Bool(b) => write!(f, "{b}"),
+            Val::List(l) => write!(f, "[{}]", l.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+const WEEKDAYS: [(&str, &str); 7] = [
+    ("Mon", "Monday"), ("Tue", "Tuesday"), ("Wed", "Wednesday"), ("Thu", "Thursday"),
+    ("Fri", "Friday"), ("Sat", "Saturday"), ("Sun", "Sunday"),
+];
+
+const TIME_VARS: [&str; 6] = ["time.year", "time.month", "time.day", "time.hour", "time.minute", "time.weekday"];
+
+/// 函数名与参数个数（None 为不限）
+const FUNCTIONS: [(&str, Option<usize>); 12] = [
+    ("has", Some(1)), ("count", Some(1)), ("tagged", Some(1)), ("knows", Some(1)), ("equipped", Some(1)),
+    ("seen", Some(1)),
+    ("min", None), ("max", None), ("abs", Some(1)), ("floor", Some(1)), ("ceil", Some(1)), ("random", Some(0)),
+];
+
+fn weekday(name: &str) -> Option<&'static str> {
+    WEEKDAYS.iter()
+        .find(|(short, long)| short.eq_ignore_ascii_case(name) || long.eq_ignore_ascii_case(name))
+        .map(|(short, _)| *short)
+}
+
+// ---------------- 解析 ----------------
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub src: String,
+    pub pos: usize, // 字符位置
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "表达式第 {} 个字符处{}\n    {}\n    {}^", self.pos + 1, self.msg, self.src, " ".repeat(
+            self.src.chars().take(self.pos).map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+        ))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Sym(&'static str),
+    End,
+}
+
+const SYMBOLS: [&str; 19] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "/", "%", "(", ")", "[", "]", ",",
+];
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = src.chars().collect();
+    let err = |pos: usize, msg: String| ParseError { src: src.to_string(), pos, msg };
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') { i += 1; }
+            let text: String = chars[start..i].iter().collect();
+            let num = text.parse().map_err(|_| err(start, format!("无法识别数字 {text}")))?;
+            tokens.push((Token::Num(num), start));
+        } else if c == '\'' || c == '"' {
+            // 反斜杠转义：\' \" \\ \n
+            let mut s = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                    match chars.get(i) {
+                        Some('n') => s.push('\n'),
+                        Some(e @ ('\'' | '"' | '\\')) => s.push(*e),
+                        Some(e) => return Err(err(i - 1, format!("无法识别的转义 \\{e}"))),
+                        None => break,
+                    }
+                } else {
+                    s.push(chars[i]);
+                }
+                i += 1;
+            }
+            if i >= chars.len() { return Err(err(start, "字符串没有结束".into())); }
+            tokens.push((Token::Str(s), start));
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), start));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let Some(sym) = SYMBOLS.iter().find(|sym| rest.starts_with(**sym))
+                else { return Err(err(start, format!("无法识别的字符 {c}"))); };
+            i += sym.chars().count();
+            tokens.push((Token::Sym(sym), start));
+        }
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<(Token, usize)>,
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token { &self.tokens[self.at].0 }
+
+    fn next(&mut self) -> Token {
+        let ret = self.tokens[self.at].0.clone();
+        if ret != Token::End { self.at += 1; }
+        ret
+    }
+
+    fn err(&self, msg: impl Into<String>) -> ParseError {
+        ParseError { src: self.src.to_string(), pos: self.tokens[self.at].1, msg: msg.into() }
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        if self.peek() == &Token::Sym(SYMBOLS.iter().find(|s| **s == sym).unwrap()) {
+            self.at += 1;
+            true
+        } else { false }
+    }
+
+    fn expect(&mut self, sym: &str) -> Result<(), ParseError> {
+        if self.eat(sym) { Ok(()) } else { Err(self.err(format!("应为 {sym}"))) }
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Expr::Binary(BinOp::Or, Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.cmp()?;
+        while self.eat("&&") {
+            lhs = Expr::Binary(BinOp::And, Box::new(lhs), Box::new(self.cmp()?));
+        }
+        Ok(lhs)
+    }
+
+    fn cmp(&mut self) -> Result<Expr, ParseError> {
+        let lhs = self.add()?;
+        let op = match self.peek() {
+            Token::Sym("==") => BinOp::Eq,
+            Token::Sym("!=") => BinOp::Ne,
+            Token::Sym("<") => BinOp::Lt,
+            Token::Sym("<=") => BinOp::Le,
+            Token::Sym(">") => BinOp::Gt,
+            Token::Sym(">=") => BinOp::Ge,
+            Token::Ident(s) if s == "in" => BinOp::In,
+            _ => return Ok(lhs),
+        };
+        self.next();
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.add()?)))
+    }
+
+    fn add(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.mul()?;
+        loop {
+            let op = match self.peek() {
+                Token::Sym("+") => BinOp::Add,
+                Token::Sym("-") => BinOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.mul()?));
+        }
+    }
+
+    fn mul(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Token::Sym("*") => BinOp::Mul,
+                Token::Sym("/") => BinOp::Div,
+                Token::Sym("%") => BinOp::Rem,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat("!") { return Ok(Expr::Unary(UnOp::Not, Box::new(self.unary()?))); }
+        if self.eat("-") { return Ok(Expr::Unary(UnOp::Neg, Box::new(self.unary()?))); }
+        self.primary()
+    }
+
+    fn list(&mut self, close: &str) -> Result<Vec<Expr>, ParseError> {
+        let mut ret = vec![];
+        if self.eat(close) { return Ok(ret); }
+        loop {
+            ret.push(self.or()?);
+            if self.eat(close) { return Ok(ret); }
+            self.expect(",")?;
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let pos = self.at;
+        match self.next() {
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::Str(s) => Ok(Expr::Str(s)),
+            Token::Ident(s) if s == "true" => Ok(Expr::Bool(true)),
+            Token::Ident(s) if s == "false" => Ok(Expr::Bool(false)),
+            Token::Ident(s) => {
+                if self.eat("(") { Ok(Expr::Call(s, self.list(")")?)) } else { Ok(Expr::Var(s)) }
+            },
+            Token::Sym("(") => {
+                let ret = self.or()?;
+                self.expect(")")?;
+                Ok(ret)
+            },
+            Token::Sym("[") => Ok(Expr::List(self.list("]")?)),
+            Token::End => { self.at = pos; Err(self.err("表达式不完整")) },
+            _ => { self.at = pos; Err(self.err("此处不应出现该符号")) },
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser { src, tokens: tokenize(src)?, at: 0 };
+        let ret = parser.or()?;
+        if parser.peek() != &Token::End {
+            return Err(parser.err("多余的内容"));
+        }
+        Ok(ret)
+    }
+
+    // ---------------- 检查 ----------------
+
+    /// 读取数据时的检查：变量、函数、以及函数参数中写死的物品与配方是否存在
+    pub fn validate(&self, data: &GameData) -> Vec<String> {
+        let mut errs = vec![];
+        self.visit(&mut |expr| match expr {
+            Expr::Var(name) => {
+                let attr = name.strip_prefix("attr.").unwrap_or(name);
+                if !(Self::builtin(name) || weekday(name).is_some()
+                    || data.player.iter().any(|a| a.name == attr)) {
+                        errs.push(format!("表达式中的未知变量 {name}"));
+                }
+            },
+            Expr::Call(name, args) => {
+                match FUNCTIONS.iter().find(|(f, _)| f == name) {
+                    None => errs.push(format!("表达式中的未知函数 {name}")),
+                    Some((_, Some(n))) if *n != args.len() =>
+                        errs.push(format!("函数 {name} 需要 {n} 个参数")),
+                    _ => (),
+                }
+                match (name.as_str(), args.first()) {
+                    ("has" | "count" | "equipped", Some(Expr::Str(id))) if !data.has_item(id) =>
+                        errs.push(format!("未知物品 {id}")),
+                    ("knows", Some(Expr::Str(id))) if !data.has_recipe(id) =>
+                        errs.push(format!("未知配方 {id}")),
+                    ("seen", Some(Expr::Str(id))) if !data.events.iter().any(|evt| &evt.name == id) =>
+                        errs.push(format!("未知事件 {id}")),
+                    _ => (),
+                }
+            },
+            _ => (),
+        });
+        errs
+    }
+
+    /// 把指向属性的变量换成属性序号，省得每次求值都按名字去找。在 validate 之后调用
+    pub fn compile(&mut self, attrs: &[String]) {
+        match self {
+            Expr::Var(name) if !Self::builtin(name) => {
+                let attr = name.strip_prefix("attr.").unwrap_or(name);
+                if let Some(index) = attrs.iter().position(|a| a == attr) {
+                    *self = Expr::Attr(attr.to_string(), index);
+                }
+            },
+            Expr::List(list) | Expr::Call(_, list) => list.iter_mut().for_each(|e| e.compile(attrs)),
+            Expr::Unary(_, e) => e.compile(attrs),
+            Expr::Binary(_, l, r) => { l.compile(attrs); r.compile(attrs); },
+            _ => (),
+        }
+    }
+
+    /// 不是属性的内置变量，和属性重名时以它们为准
+    fn builtin(name: &str) -> bool {
+        name == "money" || name == "location" || TIME_VARS.contains(&name)
+    }
+
+    fn visit(&self, f: &mut impl FnMut(&Expr)) {
+        f(self);
+        match self {
+            Expr::List(list) | Expr::Call(_, list) => list.iter().for_each(|e| e.visit(f)),
+            Expr::Unary(_, e) => e.visit(f),
+            Expr::Binary(_, l, r) => { l.visit(f); r.visit(f); },
+            _ => (),
+        }
+    }
+
+    // ---------------- 求值 ----------------
+
+    pub fn eval(&self, systems: &Systems, player: &Player) -> Result<Val, String> {
+        Ok(match self {
+            Expr::Num(n) => Val::Num(*n),
+            Expr::Str(s) => Val::Str(s.clone()),
+            Expr::Bool(b) => Val::Bool(*b),
+            Expr::List(list) => Val::List(
+                list.iter().map(|e| e.eval(systems, player)).collect::<Result<_, _>>()?
+            ),
+            Expr::Var(name) => Self::var(name, systems, player)?,
+            Expr::Attr(name, index) => Val::Num(player.attribute_at(systems, name, Some(*index))
+                .ok_or(format!("未知变量 {name}"))? as f64),
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|e| e.eval(systems, player)).collect::<Result<Vec<_>, _>>()?;
+                Self::call(name, &args, systems, player)?
+            },
+            Expr::Unary(UnOp::Not, e) => Val::Bool(!e.eval(systems, player)?.truthy()),
+            Expr::Unary(UnOp::Neg, e) => Val::Num(-e.eval(systems, player)?.num()?),
+            Expr::Binary(BinOp::And, l, r) => Val::Bool(
+                l.eval(systems, player)?.truthy() && r.eval(systems, player)?.truthy()
+            ),
+            Expr::Binary(BinOp::Or, l, r) => Val::Bool(
+                l.eval(systems, player)?.truthy() || r.eval(systems, player)?.truthy()
+            ),
+            Expr::Binary(op, l, r) => Self::binary(*op, l.eval(systems, player)?, r.eval(systems, player)?)?,
+        })
+    }
+
+    fn var(name: &str, systems: &Systems, player: &Player) -> Result<Val, String> {
+        let time = player.game_time;
+        Ok(match name {
+            "money" => Val::Num(player.money as f64),
+            "location" => Val::Str(player.game_map.clone()),
+            "time.year" => Val::Num(time.year() as f64),
+            "time.month" => Val::Num(time.month() as f64),
+            "time.day" => Val::Num(time.day() as f64),
+            "time.hour" => Val::Num(time.hour() as f64),
+            "time.minute" => Val::Num(time.minute() as f64),
+            "time.weekday" => Val::Str(time.weekday().to_string()),
+            _ => {
+                let attr = name.strip_prefix("attr.").unwrap_or(name);
+                if let Some(val) = player.attribute(systems, attr) {
+                    Val::Num(val as f64)
+                } else if let Some(day) = weekday(name) {
+                    Val::Str(day.to_string())
+                } else {
+                    return Err(format!("未知变量 {name}"));
+                }
+            },
+        })
+    }
+
+    fn call(name: &str, args: &[Val], systems: &Systems, player: &Player) -> Result<Val, String> {
+        let str_arg = || match args.first() {
+            Some(Val::Str(s)) => Ok(s.as_str()),
+            _ => Err(format!("函数 {name} 需要一个字符串参数")),
+        };
+        let num_arg = || args.first().ok_or(format!("函数 {name} 需要一个参数"))?.num();
+        let count = |id: &str| player.items.get(id).map_or(0, |(_, num)| *num);
+        Ok(match name {
+            "has" => Val::Bool(count(str_arg()?) > 0),
+            "count" => Val::Num(count(str_arg()?) as f64),
+            "tagged" => {
+                let tag = str_arg()?;
+                Val::Num(player.items.iter()
+                    .filter(|(id, (val, _))| systems.item.has_tag(id, val, tag))
+                    .map(|(_, (_, num))| *num)
+                    .sum::<usize>() as f64)
+            },
+            "knows" => Val::Bool(systems.craft.is_known(player, str_arg()?)),
+            "seen" => Val::Num(player.history.get(str_arg()?).map_or(0, |r| r.visits) as f64),
+            "equipped" => {
+                let id = str_arg()?;
+                Val::Bool(player.equipment.values().any(|equipped| equipped == id))
+            },
+            "min" | "max" => {
+                let nums = args.iter().map(|v| v.num()).collect::<Result<Vec<_>, _>>()?;
+                let fold = if name == "min" { f64::min } else { f64::max };
+                Val::Num(nums.into_iter().reduce(fold).ok_or(format!("函数 {name} 至少需要一个参数"))?)
+            },
+            "abs" => Val::Num(num_arg()?.abs()),
+            "floor" => Val::Num(num_arg()?.floor()),
+            "ceil" => Val::Num(num_arg()?.ceil()),
+            "random" => Val::Num(player.rng.next_f64()),
+            _ => return Err(format!("未知函数 {name}")),
+        })
+    }
+
+    fn binary(op: BinOp, l: Val, r: Val) -> Result<Val, String> {
+        use std::cmp::Ordering;
+        let ord = |l: &Val, r: &Val| -> Result<Ordering, String> {
+            match (l, r) {
+                (Val::Str(a), Val::Str(b)) => Ok(a.cmp(b)),
+                _ => l.num()?.partial_cmp(&r.num()?).ok_or("无法比较".into()),
+            }
+        };
+        Ok(match op {
+            BinOp::Eq => Val::Bool(l == r),
+            BinOp::Ne => Val::Bool(l != r),
+            BinOp::Lt => Val::Bool(ord(&l, &r)?.is_lt()),
+            BinOp::Le => Val::Bool(ord(&l, &r)?.is_le()),
+            BinOp::Gt => Val::Bool(ord(&l, &r)?.is_gt()),
+            BinOp::Ge => Val::Bool(ord(&l, &r)?.is_ge()),
+            BinOp::In => match (&l, &r) {
+                (_, Val::List(list)) => Val::Bool(list.contains(&l)),
+                (Val::Str(a), Val::Str(b)) => Val::Bool(b.contains(a.as_str())),
+                _ => return Err(format!("{r} 不是列表或字符串")),
+            },
+            BinOp::Add => match (&l, &r) {
+                (Val::Str(a), Val::Str(b)) => Val::Str(format!("{a}{b}")),
+                _ => Val::Num(l.num()? + r.num()?),
+            },
+            BinOp::Sub => Val::Num(l.num()? - r.num()?),
+            BinOp::Mul => Val::Num(l.num()? * r.num()?),
+            BinOp::Div => Val::Num(l.num()? / r.num()?),
+            BinOp::Rem => Val::Num(l.num()? % r.num()?),
+            BinOp::And | BinOp::Or => unreachable!(),
+        })
+    }
+}
+
+/// 数值：可以直接写数，也可以写表达式字符串，如 { Add = "max(5, energy / 10)" }
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "NumberRepr")]
+pub enum Number {
+    Const(f64),
+    Expr(Expr),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberRepr {
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl TryFrom<NumberRepr> for Number {
+    type Error = ParseError;
+    fn try_from(value: NumberRepr) -> Result<Self, Self::Error> {
+        Ok(match value {
+            NumberRepr::Int(i) => Number::Const(i as f64),
+            NumberRepr::Float(f) => Number::Const(f),
+            NumberRepr::Str(s) => Number::Expr(Expr::parse(&s)?),
+        })
+    }
+}
+
+impl Number {
+    /// 求值出错时按 0 处理并打印错误，免得一个写错的数值让整个游戏停下来
+    pub fn eval(&self, systems: &Systems, player: &Player) -> f64 {
+        match self {
+            Number::Const(n) => *n,
+            Number::Expr(expr) => expr.eval(systems, player)
+                .and_then(|v| v.num())
+                .unwrap_or_else(|e| { eprintln!("ERROR: {e}"); 0. }),
+        }
+    }
+
+    pub fn compile(&mut self, attrs: &[String]) {
+        if let Number::Expr(expr) = self { expr.compile(attrs); }
+    }
+
+    pub fn validate(&self, data: &GameData) -> Vec<String> {
+        match self {
+            Number::Const(_) => vec![],
+            Number::Expr(expr) => expr.validate(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn eval(src: &str) -> Result<Val, String> {
+        let (_, systems, player) = testing::setup("");
+        Expr::parse(src).map_err(|e| e.to_string())?.eval(&systems, &player)
+    }
+
+    fn parse_err(src: &str) -> (usize, String) {
+        let e = Expr::parse(src).unwrap_err();
+        (e.pos, e.msg)
+    }
+
+    fn var(name: &str) -> Box<Expr> { Box::new(Expr::Var(name.into())) }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(Expr::parse("a || b && c").unwrap(),
+            Expr::Binary(BinOp::Or, var("a"), Box::new(Expr::Binary(BinOp::And, var("b"), var("c")))));
+        assert_eq!(Expr::parse("a && b || c").unwrap(),
+            Expr::Binary(BinOp::Or, Box::new(Expr::Binary(BinOp::And, var("a"), var("b"))), var("c")));
+        assert_eq!(eval("true || false && false"), Ok(Val::Bool(true)));
+        assert_eq!(eval("(true || false) && false"), Ok(Val::Bool(false)));
+        assert_eq!(eval("1 + 2 * 3 - 4 / 2"), Ok(Val::Num(5.)));
+        assert_eq!(eval("10 - 3 - 2"), Ok(Val::Num(5.)));
+        assert_eq!(eval("1 + 2 > 2 && 7 % 4 == 3"), Ok(Val::Bool(true)));
+    }
+
+    #[test]
+    fn unary() {
+        assert_eq!(eval("-3 + 5"), Ok(Val::Num(2.)));
+        assert_eq!(eval("--3"), Ok(Val::Num(3.)));
+        assert_eq!(eval("-(1 + 2) * 2"), Ok(Val::Num(-6.)));
+        assert_eq!(eval("!true"), Ok(Val::Bool(false)));
+        assert_eq!(eval("!!1"), Ok(Val::Bool(true)));
+        assert_eq!(eval("!''"), Ok(Val::Bool(true)));
+        assert_eq!(eval("!false && false"), Ok(Val::Bool(false)));
+        assert_eq!(eval("-health"), Ok(Val::Num(-80.)));
+        assert!(eval("-'a'").is_err());
+    }
+
+    #[test]
+    fn membership() {
+        assert_eq!(eval("2 in [1, 2, 3]"), Ok(Val::Bool(true)));
+        assert_eq!(eval("4 in [1, 2, 3]"), Ok(Val::Bool(false)));
+        assert_eq!(eval("'b' in ['a', 'b']"), Ok(Val::Bool(true)));
+        assert_eq!(eval("1 in []"), Ok(Val::Bool(false)));
+        assert_eq!(eval("'ell' in 'hello'"), Ok(Val::Bool(true)));
+        assert_eq!(eval("'x' in 'hello'"), Ok(Val::Bool(false)));
+        assert_eq!(eval("Mon in [Mon, Wed]"), Ok(Val::Bool(true)));
+        assert!(eval("1 in 2").is_err());
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(eval(r#""a'b""#), Ok(Val::Str("a'b".into())));
+        assert_eq!(eval(r"'it\'s'"), Ok(Val::Str("it's".into())));
+        assert_eq!(eval(r#""say \"hi\"""#), Ok(Val::Str("say \"hi\"".into())));
+        assert_eq!(eval(r"'a\\b'"), Ok(Val::Str("a\\b".into())));
+        assert_eq!(eval(r"'a\nb'"), Ok(Val::Str("a\nb".into())));
+        assert_eq!(eval("'中文' + '字符串'"), Ok(Val::Str("中文字符串".into())));
+        assert_eq!(parse_err("'abc"), (0, "字符串没有结束".into()));
+        assert_eq!(parse_err("x == \"abc"), (5, "字符串没有结束".into()));
+        assert_eq!(parse_err(r"'abc\'"), (0, "字符串没有结束".into()));
+        assert_eq!(parse_err(r"'abc\"), (0, "字符串没有结束".into()));
+        assert_eq!(parse_err(r"'a\qb'"), (2, "无法识别的转义 \\q".into()));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(parse_err("1 +"), (3, "表达式不完整".into()));
+        assert_eq!(parse_err("a && && b"), (5, "此处不应出现该符号".into()));
+        assert_eq!(parse_err("(1 + 2"), (6, "应为 )".into()));
+        assert_eq!(parse_err("[1, 2"), (5, "应为 ,".into()));
+        assert_eq!(parse_err("f(1 2)"), (4, "应为 ,".into()));
+        assert_eq!(parse_err("1 2"), (2, "多余的内容".into()));
+        assert_eq!(parse_err("a # b"), (2, "无法识别的字符 #".into()));
+        assert_eq!(parse_err("1.2.3"), (0, "无法识别数字 1.2.3".into()));
+        // 位置按字符算，不按字节
+        assert_eq!(parse_err("'体力' ?"), (5, "无法识别的字符 ?".into()));
+        let shown = Expr::parse("体力 > ?").unwrap_err().to_string();
+        assert!(shown.starts_with("表达式第 6 个字符处无法识别的字符 ?"));
+        assert!(shown.ends_with("\n    体力 > ?\n           ^"), "{shown}");
+    }
+}
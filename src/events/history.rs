@@ -0,0 +1,41 @@
+// history.rs
+// 事件经历：每个事件走过几次、每个段落到过几次、选过哪些选项、最近一次是什么时候。
+// 存在 Player 上，跟着存档走。
+
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub visits: usize,                       // 离开该事件的次数。被物品事件打断再回来不算
+    pub segments: HashMap<String, usize>,    // 段落 -> 走过几次
+    pub choices: HashMap<String, Vec<usize>>, // 段落 -> 每个选项（按序号）被选过几次
+    pub last: Option<NaiveDateTime>,         // 最近一次离开时的游戏时间
+}
+
+impl EventRecord {
+    /// 走完一个段落；option 为选中的选项序号，没有选项的段落为 None
+    pub fn pass_segment(&mut self, segment: &str, option: Option<usize>) {
+        *self.segments.entry(segment.to_string()).or_default() += 1;
+        if let Some(i) = option {
+            let choices = self.choices.entry(segment.to_string()).or_default();
+            if choices.len() <= i { choices.resize(i + 1, 0); }
+            choices[i] += 1;
+        }
+    }
+
+    pub fn leave(&mut self, time: NaiveDateTime) {
+        self.visits += 1;
+        self.last = Some(time);
+    }
+
+    pub fn segment_visits(&self, segment: &str) -> usize {
+        self.segments.get(segment).copied().unwrap_or(0)
+    }
+
+    pub fn chosen(&self, segment: &str, option: usize) -> usize {
+        self.choices.get(segment).and_then(|c| c.get(option)).copied().unwrap_or(0)
+    }
+}
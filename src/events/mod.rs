@@ -1,4 +1,10 @@
+pub mod changes;
 pub mod conditions;
 pub mod events;
+pub mod expr;
+pub mod history;
+pub mod named;
+pub mod schedule;
+pub mod template;
 pub mod triggers;
 pub mod modifier;
\ No newline at end of file
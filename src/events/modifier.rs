@@ -1,8 +1,9 @@
+use anyhow::anyhow;
 use serde::Deserialize;
 
-use crate::{player::Player, systems::Systems};
+use crate::{game::GameData, player::Player, systems::{craft_system::CraftSystem, script_system::{Script, ScriptSystem}, shop_system::ShopSystem, Systems}};
 
-use super::conditions::Condition;
+use super::{changes::Footprint, conditions::Condition, expr::Number, named::Definitions, schedule::{Delay, Pending}, triggers::Trigger};
 
 #[derive(Default,Deserialize,Clone,Debug)]
 #[serde(untagged)]
@@ -10,6 +11,19 @@ pub enum Modifier {
     Attribute { attr: Identity, val: ValModifier },
     Item { item: String, modify: ItemModifier },
     Position { towards: String, #[serde(default)] check: bool },
+    Equip { equip: String },     // 物品 id，装到它定义的装备栏上，顶掉原有的
+    Unequip { unequip: String }, // 装备栏
+    Money { money: i32 },
+    Buy { buy: String, shop: String, #[serde(default = "Modifier::one")] count: usize },
+    Sell { sell: String, shop: String, #[serde(default = "Modifier::one")] count: usize },
+    Learn { learn: String },  // 学会配方
+    Craft { craft: String },  // 按配方做一次
+    Script { script: Script }, // 运行脚本，见 script_system.rs
+    Named { named: String },   // 引用 GameData.modifiers 里的定义，读取数据时展开，见 named.rs
+    Trigger { trigger: Trigger }, // 立即触发该触发器，选中的事件在这个选项（或物品）之后马上开始
+    Schedule { schedule: Pending, #[serde(default)] after: Delay }, // 过一段时间再发生，见 schedule.rs
+    Wait { wait: Delay },      // 让时间过去，如 { wait = { hours = 2 } }
+    Hidden { hidden: Box<Modifier> }, // 照常执行，但不提示玩家发生了什么变化
 
     Group(Vec<Modifier>),
     Condition{group: Vec<Modifier>,cond: Option<Condition>},
@@ -28,20 +42,127 @@ pub enum Identity {
 
 #[derive(Default,Deserialize,Clone,Debug)]
 pub enum ValModifier {
-    Add(i32),
-    Mul(f32),
+    Add(Number), // 数值可以是表达式，如 { Add = "energy / 10" }
+    Mul(Scale),  // { Mul = 1.5 }，或者指定取整方式 { Mul = { by = 1.5, round = "Up" } }
+    Div(Scale),  // 同上。除数为 0 时不变
+    Set(Number),
+    AddRandom(Number, Number), // 加上 [lo, hi] 之间的随机整数，如 { AddRandom = [5, 15] }
+    PercentOfMax(Scale),       // 加上该属性上限的百分之几，如 { PercentOfMax = 10 }
+    Min(Number),               // 和它取小，即不超过它
+    Max(Number),               // 和它取大，即不低于它
+    Clamp(Number, Number),     // 限制在闭区间内
+    Copy(String),              // 取另一个属性的基础值（不含装备加成，免得加成被算两次）
     Sqrt10, // 钱学森先生发明的计分法，再次呈现！
     #[default]
     None
 }
 
+/// 乘除用的数，可以带上取整方式
+#[derive(Deserialize,Clone,Debug)]
+#[serde(untagged)]
+pub enum Scale {
+    Plain(Number),
+    Rounded { by: Number, #[serde(default)] round: Rounding },
+}
+
+#[derive(Default,Deserialize,Clone,Copy,Debug)]
+pub enum Rounding {
+    #[default]
+    Truncate, // 向零取整，和以前的 Mul 一样
+    Down,     // 向下
+    Up,       // 向上
+    Nearest,  // 四舍五入
+}
+
+impl Rounding {
+    pub fn apply(self, val: f64) -> i32 {
+        let val = match self {
+            Rounding::Truncate => val.trunc(),
+            Rounding::Down => val.floor(),
+            Rounding::Up => val.ceil(),
+            Rounding::Nearest => val.round(),
+        };
+        val as i32
+    }
+}
+
+impl Scale {
+    fn eval(&self, systems: &Systems, player: &Player) -> (f64, Rounding) {
+        match self {
+            Scale::Plain(by) => (by.eval(systems, player), Rounding::default()),
+            Scale::Rounded { by, round } => (by.eval(systems, player), *round),
+        }
+    }
+
+    fn by(&self) -> &Number {
+        match self {
+            Scale::Plain(by) | Scale::Rounded { by, .. } => by,
+        }
+    }
+
+    fn by_mut(&mut self) -> &mut Number {
+        match self {
+            Scale::Plain(by) | Scale::Rounded { by, .. } => by,
+        }
+    }
+}
+
 impl ValModifier {
-    pub fn apply(&self, val: &mut i32) {
+    /// 返回修改后的值。表达式按修改前的玩家状态求值；max 为属性上限，不是属性时为 None
+    pub fn apply(&self, val: i32, max: Option<i32>, systems: &Systems, player: &Player) -> i32 {
+        let num = |num: &Number| num.eval(systems, player) as i32;
         match self {
-            Self::Add(add) => {* val += add; }
-            Self::Mul(mul) => {*val = (*val as f32 * mul) as i32}
-            Self::Sqrt10 => {*val = ((*val as f32).sqrt()*10.) as i32}
-            Self::None => ()
+            Self::Add(add) => val + num(add),
+            Self::Mul(mul) => {
+                let (by, round) = mul.eval(systems, player);
+                round.apply(val as f64 * by)
+            },
+            Self::Div(div) => match div.eval(systems, player) {
+                (0., _) => { eprintln!("ERROR: 除数为 0"); val },
+                (by, round) => round.apply(val as f64 / by),
+            },
+            Self::Set(set) => num(set),
+            Self::AddRandom(lo, hi) => val + player.rng.range(num(lo) as i64, num(hi) as i64) as i32,
+            Self::PercentOfMax(percent) => {
+                let Some(max) = max else { eprintln!("ERROR: 这里没有上限可取"); return val; };
+                let (percent, round) = percent.eval(systems, player);
+                val + round.apply(max as f64 * percent / 100.)
+            },
+            Self::Min(min) => val.min(num(min)),
+            Self::Max(max) => val.max(num(max)),
+            Self::Clamp(lo, hi) => {
+                let (lo, hi) = (num(lo), num(hi));
+                val.clamp(lo.min(hi), lo.max(hi))
+            },
+            Self::Copy(attr) => player.attributes.get(attr).copied()
+                .unwrap_or_else(|| { eprintln!("ERROR: 未知属性 {attr}"); val }),
+            Self::Sqrt10 => ((val as f32).sqrt()*10.) as i32,
+            Self::None => val
+        }
+    }
+
+    pub fn compile(&mut self, attrs: &[String]) {
+        match self {
+            Self::Add(num) | Self::Set(num) | Self::Min(num) | Self::Max(num) => num.compile(attrs),
+            Self::Mul(scale) | Self::Div(scale) | Self::PercentOfMax(scale) => scale.by_mut().compile(attrs),
+            Self::AddRandom(lo, hi) | Self::Clamp(lo, hi) => { lo.compile(attrs); hi.compile(attrs); },
+            Self::Copy(_) | Self::Sqrt10 | Self::None => (),
+        }
+    }
+
+    pub fn validate(&self, data: &GameData) -> Vec<String> {
+        match self {
+            Self::Add(num) | Self::Set(num) | Self::Min(num) | Self::Max(num) => num.validate(data),
+            Self::Mul(scale) | Self::PercentOfMax(scale) => scale.by().validate(data),
+            Self::Div(scale) => match scale.by() {
+                Number::Const(by) if *by == 0. => vec!["除数为 0".to_string()],
+                by => by.validate(data),
+            },
+            Self::AddRandom(lo, hi) | Self::Clamp(lo, hi) => lo.validate(data).into_iter()
+                .chain(hi.validate(data))
+                .collect(),
+            Self::Copy(attr) if !data.player.iter().any(|a| &a.name == attr) => vec![format!("未知属性 {attr}")],
+            _ => vec![],
         }
     }
 }
@@ -49,17 +170,34 @@ impl ValModifier {
 #[derive(Default,Deserialize,Clone,Debug)]
 #[serde(untagged)]
 pub enum ItemModifier {
-    Add { add: usize, val: Option<toml::Value> },
+    Add { add: usize, val: Option<toml::Value>, #[serde(default)] overflow: Overflow },
     Sub { sub: usize, val: Option<toml::Value> },
     ModifyVal { val: toml::Value},
     #[default]
     None
 }
 
+/// 放不下时怎么办
+#[derive(Default,Deserialize,Clone,Debug)]
+pub enum Overflow {
+    #[default]
+    Cap,              // 能放多少放多少
+    Reject,           // 一个都不给
+    Trigger(Trigger), // 一个都不给，并触发该触发器，交给事件去处理
+}
+
 impl ItemModifier {
-    pub fn apply(&self, (value,num): &mut (toml::Value,usize)) {
+    /// space 为还能放下的数量，None 为不限。放不下且需要触发时返回触发器。
+    pub fn apply(&self, (value,num): &mut (toml::Value,usize), space: Option<usize>) -> Option<Trigger> {
         match self {
-            ItemModifier::Add { add, val } => {
+            ItemModifier::Add { add, val, overflow } => {
+                let add = match (space, overflow) {
+                    (Some(space), Overflow::Cap) if space < *add => space,
+                    (Some(space), Overflow::Reject) if space < *add => return None,
+                    (Some(space), Overflow::Trigger(trigger)) if space < *add => return Some(trigger.clone()),
+                    _ => *add,
+                };
+                if add == 0 { return None; }
                 *num += add;
                 if let Some(val) = val { *value = val.clone(); }
             },
@@ -71,23 +209,82 @@ impl ItemModifier {
             ItemModifier::ModifyVal { val } => { *value = val.clone() },
             ItemModifier::None => (),
         }
+        None
     }
 }
 
 impl Modifier {
+    fn one() -> usize { 1 }
+
+    /// 执行修改。每一步造成的变化记到 player.changes，见 changes.rs
     pub fn modify(
         &self, 
         systems: &Systems,
         player: &mut Player
+    ) -> anyhow::Result<()> {
+        match self {
+            Modifier::Group(_) | Modifier::Condition { .. } | Modifier::Hidden { .. } | Modifier::None =>
+                self.run(systems, player),
+            _ => {
+                let before = Footprint::of(player);
+                self.run(systems, player)?;
+                before.record(player);
+                Ok(())
+            },
+        }
+    }
+
+    fn run(
+        &self, 
+        systems: &Systems,
+        player: &mut Player
     ) -> anyhow::Result<()> {
         // let trigger = &mut player.trigger;
         match &self {
             Modifier::Attribute { attr, val } => {
-                player.modify_attribute(attr, val); 
+                player.modify_attribute(systems, attr, val);
             },
             Modifier::Item { item, modify } => {
-                let Some(val) = player.items.get_mut(item) else { return Ok(()); };
-                modify.apply(val); if val.1 == 0 { player.items.remove(item); }
+                let space = systems.item.free_space(&player.items, Some(item));
+                let val = if let ItemModifier::Add { .. } = modify {
+                    // 新获得的物品从空的覆盖表开始，属性全部取自定义
+                    player.items.entry(item.clone())
+                        .or_insert_with(|| (toml::Value::Table(toml::Table::new()), 0))
+                } else {
+                    let Some(val) = player.items.get_mut(item) else { return Ok(()); };
+                    val
+                };
+                if let Some(trigger) = modify.apply(val, space) { player.trigger.insert(trigger); }
+                if val.1 == 0 {
+                    player.items.remove(item);
+                    player.equipment.retain(|_, id| id != item);
+                }
+            },
+            Modifier::Equip { equip } => {
+                let Some(def) = systems.item.items.get(equip).and_then(|def| def.equip.as_ref())
+                    else { return Err(anyhow!("物品 {equip} 不可装备")); };
+                if !player.items.contains_key(equip) {
+                    return Err(anyhow!("没有{}，装备不上", systems.item.displayed_name(equip)));
+                }
+                player.equipment.insert(def.slot.clone(), equip.clone());
+            },
+            Modifier::Unequip { unequip } => { player.equipment.remove(unequip); },
+            Modifier::Money { money } => { player.money += money; },
+            Modifier::Buy { buy, shop, count } => ShopSystem::buy(systems, player, shop, buy, *count)?,
+            Modifier::Sell { sell, shop, count } => ShopSystem::sell(systems, player, shop, sell, *count)?,
+            Modifier::Learn { learn } => { player.known_recipes.insert(learn.clone()); },
+            Modifier::Craft { craft } => CraftSystem::craft(systems, player, craft)?,
+            Modifier::Script { script } => ScriptSystem::modify(systems, script, player)?,
+            Modifier::Named { named } => return Err(anyhow!("具名修改 {named} 未展开")),
+            Modifier::Trigger { trigger } => if let Some(evt) = systems.trigger.fire(trigger, systems, player) {
+                player.evt_stack.push((evt, None)); // 由 EventSystem::take_fired 取出
+            },
+            Modifier::Schedule { schedule, after } => player.schedule(player.game_time + after.duration(), schedule.clone()),
+            Modifier::Wait { wait } => systems.time.advance(player, wait.duration()),
+            Modifier::Hidden { hidden } => {
+                let shown = player.changes.len();
+                hidden.modify(systems, player)?;
+                player.changes.truncate(shown);
             },
             Modifier::Group(group) => {
                 for modifier in group {
@@ -104,10 +301,267 @@ impl Modifier {
             Modifier::None => (),
             Modifier::Position { towards, check } => {
                 if *check {
-                    systems.map.travel(player, &towards, &systems.time)?;
+                    systems.map.travel(player, &towards, systems)?;
                 } else { player.game_map = towards.clone() }
             },
         };
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// 要么全部生效，要么出错时把玩家恢复原样，不会改了一半。
+    /// 需要整体成败的地方（选项、使用物品）用这个，modify 只管往下执行
+    pub fn apply(&self, systems: &Systems, player: &mut Player) -> anyhow::Result<()> {
+        if let Modifier::None = self { return Ok(()); }
+        // 账本可能有几千条，不进快照；出错时只截掉这次记下的
+        let ledger = std::mem::take(&mut player.ledger);
+        let snapshot = player.clone();
+        player.ledger = ledger;
+        let checkpoint = player.ledger.checkpoint();
+        self.modify(systems, player).inspect_err(|_| {
+            let ledger = std::mem::take(&mut player.ledger);
+            *player = snapshot;
+            player.ledger = ledger;
+            player.ledger.rollback(checkpoint);
+        })
+    }
+
+    /// 最多要花多少钱。选项据此自动加上“买得起”的条件
+    pub fn cost(&self, systems: &Systems) -> i32 {
+        match self {
+            Modifier::Money { money } => (-money).max(0),
+            Modifier::Buy { buy, shop, count } =>
+                systems.shop.price(shop, buy).unwrap_or(0) * *count as i32,
+            Modifier::Group(group) | Modifier::Condition { group, .. } =>
+                group.iter().map(|modifier| modifier.cost(systems)).sum(),
+            Modifier::Hidden { hidden } => hidden.cost(systems),
+            _ => 0,
+        }
+    }
+
+    /// 是否可能改动背包——改动了就要通知前端刷新
+    pub fn touches_items(&self) -> bool {
+        match self {
+            Modifier::Item { .. } | Modifier::Equip { .. } | Modifier::Unequip { .. }
+                | Modifier::Buy { .. } | Modifier::Sell { .. } | Modifier::Craft { .. }
+                | Modifier::Script { .. } => true,
+            Modifier::Group(group) | Modifier::Condition { group, .. } =>
+                group.iter().any(|modifier| modifier.touches_items()),
+            Modifier::Hidden { hidden } => hidden.touches_items(),
+            _ => false,
+        }
+    }
+
+    /// 编译其中的条件与数值表达式，见 Condition::compile
+    pub fn compile(&mut self, attrs: &[String]) {
+        match self {
+            Modifier::Attribute { val, .. } => val.compile(attrs),
+            Modifier::Group(group) => group.iter_mut().for_each(|modifier| modifier.compile(attrs)),
+            Modifier::Hidden { hidden } => hidden.compile(attrs),
+            Modifier::Condition { group, cond } => {
+                group.iter_mut().for_each(|modifier| modifier.compile(attrs));
+                if let Some(cond) = cond { cond.compile(attrs); }
+            },
+            _ => (),
+        }
+    }
+
+    /// 其中所有的脚本（包括条件里的），读取数据时编译
+    pub fn scripts(&mut self) -> Vec<&mut Script> {
+        match self {
+            Modifier::Script { script } => vec![script],
+            Modifier::Group(group) => group.iter_mut().flat_map(|modifier| modifier.scripts()).collect(),
+            Modifier::Hidden { hidden } => hidden.scripts(),
+            Modifier::Condition { group, cond } => {
+                let mut scripts: Vec<_> = group.iter_mut().flat_map(|modifier| modifier.scripts()).collect();
+                if let Some(cond) = cond { scripts.extend(cond.scripts()); }
+                scripts
+            },
+            _ => vec![],
+        }
+    }
+
+    /// 把具名条件、具名修改的引用换成定义的内容
+    pub fn resolve(&mut self, defs: &mut Definitions) -> Result<(), String> {
+        match self {
+            Modifier::Named { named } => *self = defs.modifier(named)?,
+            Modifier::Group(group) => for modifier in group { modifier.resolve(defs)?; },
+            Modifier::Hidden { hidden } => hidden.resolve(defs)?,
+            Modifier::Condition { group, cond } => {
+                for modifier in group { modifier.resolve(defs)?; }
+                if let Some(cond) = cond { cond.resolve(defs)?; }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// 读取数据时的检查，返回发现的问题
+    pub fn validate(&self, data: &GameData) -> Vec<String> {
+        match self {
+            Modifier::Named { named } => vec![format!("具名修改 {named} 未展开")],
+            Modifier::Schedule { schedule, after } => {
+                let mut errs = schedule.validate(data);
+                if after.duration() < chrono::Duration::zero() { errs.push("不能安排在过去".to_string()); }
+                errs
+            },
+            Modifier::Wait { wait } if wait.duration() < chrono::Duration::zero() => vec!["时间不能倒流".to_string()],
+            Modifier::Attribute { attr, val } => {
+                let mut errs = val.validate(data);
+                match attr {
+                    Identity::Str(name) if !data.player.iter().any(|a| &a.name == name) =>
+                        errs.push(format!("未知属性 {name}")),
+                    Identity::Index(i) if *i >= data.player.len() =>
+                        errs.push(format!("属性序号 {i} 超出范围")),
+                    _ => (),
+                }
+                errs
+            },
+            Modifier::Item { item, .. } if !data.has_item(item) => vec![format!("未知物品 {item}")],
+            Modifier::Equip { equip } => match data.items.iter().find(|item| &item.name == equip) {
+                None => vec![format!("未知物品 {equip}")],
+                Some(item) if item.equip.is_none() => vec![format!("物品 {equip} 不可装备")],
+                _ => vec![],
+            },
+            Modifier::Unequip { unequip } if !data.items.iter()
+                .any(|item| item.equip.as_ref().is_some_and(|equip| &equip.slot == unequip)) =>
+                    vec![format!("未知装备栏 {unequip}")],
+            Modifier::Buy { buy: item, shop, .. } | Modifier::Sell { sell: item, shop, .. } =>
+                match data.shops.iter().find(|s| &s.name == shop) {
+                    None => vec![format!("未知商店 {shop}")],
+                    Some(s) if !s.goods.iter().any(|goods| &goods.item == item) =>
+                        vec![format!("商店 {shop} 没有 {item}")],
+                    _ => vec![],
+                },
+            Modifier::Learn { learn: recipe } | Modifier::Craft { craft: recipe } if !data.has_recipe(recipe) =>
+                vec![format!("未知配方 {recipe}")],
+            Modifier::Group(group) => group.iter()
+                .flat_map(|modifier| modifier.validate(data))
+                .collect(),
+            Modifier::Hidden { hidden } => hidden.validate(data),
+            Modifier::Condition { group, cond } => group.iter()
+                .flat_map(|modifier| modifier.validate(data))
+                .chain(cond.iter().flat_map(|cond| cond.validate(data)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const TEA: &str = "[[items]]\nname = \"tea\"\nmax_stack = 3";
+
+    fn count(player: &Player, item: &str) -> Option<usize> {
+        player.items.get(item).map(|(_, num)| *num)
+    }
+
+    #[test]
+    fn overflow() {
+        let (data, systems, mut player) = testing::setup(TEA);
+        let add = |n: usize, overflow: &str| testing::modifier(&data, &format!("item = 'tea'\nmodify = {{ add = {n}{overflow} }}"));
+        let reject = ", overflow = 'Reject'";
+        let trigger = ", overflow = { Trigger = { t = 'Custom', c = 'full' } }";
+
+        // 一个都放不下时不留下空的条目
+        add(5, reject).apply(&systems, &mut player).unwrap();
+        assert_eq!(count(&player, "tea"), None);
+        // 放得下时几种策略都一样
+        for overflow in ["", reject, trigger] {
+            player.items.remove("tea");
+            add(2, overflow).apply(&systems, &mut player).unwrap();
+            assert_eq!(count(&player, "tea"), Some(2));
+        }
+        assert!(!player.trigger.contains(&Trigger::Custom("full".into())));
+
+        add(2, reject).apply(&systems, &mut player).unwrap();
+        assert_eq!(count(&player, "tea"), Some(2));
+        add(2, trigger).apply(&systems, &mut player).unwrap();
+        assert_eq!(count(&player, "tea"), Some(2));
+        assert!(player.trigger.contains(&Trigger::Custom("full".into())));
+        add(5, "").apply(&systems, &mut player).unwrap(); // 默认 Cap，放满为止
+        assert_eq!(count(&player, "tea"), Some(3));
+        add(1, "").apply(&systems, &mut player).unwrap();
+        assert_eq!(count(&player, "tea"), Some(3));
+    }
+
+    /// 把数值修改作用在 x 上（属性上限为 max），表达式里的 energy 为 15
+    fn eval(src: &str, x: i32, max: Option<i32>) -> i32 {
+        let (data, systems, player) = testing::setup("");
+        let Modifier::Attribute { val, .. } = testing::modifier(&data, &format!("attr = 'energy'\nval = {src}"))
+            else { panic!("不是属性修改：{src}") };
+        val.apply(x, max, &systems, &player)
+    }
+
+    #[test]
+    fn rounding() {
+        let cases = [
+            ("{ Mul = 1.5 }", 5, 7), ("{ Mul = 1.5 }", -5, -7), // 默认向零取整
+            ("{ Mul = { by = 1.5, round = 'Down' } }", -5, -8),
+            ("{ Mul = { by = 1.5, round = 'Up' } }", 5, 8),
+            ("{ Mul = { by = 1.5, round = 'Nearest' } }", 5, 8),
+            ("{ Mul = { by = 1.5, round = 'Nearest' } }", -5, -8),
+            ("{ Mul = 'energy / 10' }", 10, 15),
+            ("{ Div = 2 }", 7, 3), ("{ Div = 2 }", -7, -3),
+            ("{ Div = { by = 2, round = 'Up' } }", 7, 4),
+            ("{ Div = { by = 2, round = 'Down' } }", -7, -4),
+            ("{ Div = 'energy - 15' }", 7, 7), // 除数算出来是 0，不变
+        ];
+        for (src, x, expected) in cases {
+            assert_eq!(eval(src, x, Some(100)), expected, "{src} 作用在 {x} 上");
+        }
+    }
+
+    #[test]
+    fn percent_of_max() {
+        assert_eq!(eval("{ PercentOfMax = 10 }", 50, Some(100)), 60);
+        assert_eq!(eval("{ PercentOfMax = -25 }", 50, Some(100)), 25);
+        assert_eq!(eval("{ PercentOfMax = 15 }", 0, Some(30)), 4);
+        assert_eq!(eval("{ PercentOfMax = { by = 15, round = 'Up' } }", 0, Some(30)), 5);
+        assert_eq!(eval("{ PercentOfMax = 'energy' }", 0, Some(200)), 30);
+        assert_eq!(eval("{ PercentOfMax = 10 }", 50, None), 50); // 没有上限可取，不变
+    }
+
+    #[test]
+    fn bounds() {
+        for (x, expected) in [(5, 10), (15, 15), (25, 20)] {
+            assert_eq!(eval("{ Clamp = [10, 20] }", x, None), expected);
+            assert_eq!(eval("{ Clamp = [20, 10] }", x, None), expected); // 两端写反了也一样
+        }
+        assert_eq!(eval("{ Clamp = [0, 'energy'] }", 30, None), 15);
+        assert_eq!(eval("{ Min = 'energy' }", 30, None), 15);
+        assert_eq!(eval("{ Max = 'energy' }", 3, None), 15);
+        assert_eq!(eval("{ Set = 'energy * 2' }", 3, None), 30);
+    }
+
+    #[test]
+    fn apply_rolls_back_everything_but_earlier_ledger_entries() {
+        let (data, systems, mut player) = testing::setup(TEA);
+        testing::modifier(&data, "attr = 'energy'\nval = { Add = 5 }").apply(&systems, &mut player).unwrap();
+        assert_eq!(player.ledger.entries.len(), 1);
+        let before = (format!("{:?}", player.attributes.val), player.money, player.changes.len(), player.game_time);
+
+        // 前面几步都做了，最后一步出错：整体撤销
+        let failing = testing::modifier(&data, r#"group = [
+            { attr = "energy", val = { Add = 10 } },
+            { item = "tea", modify = { add = 1 } },
+            { money = 5 },
+            { wait = { hours = 1 } },
+            { equip = "tea" },
+        ]"#);
+        let err = failing.apply(&systems, &mut player).unwrap_err();
+        assert_eq!(err.to_string(), "物品 tea 不可装备");
+        assert_eq!((format!("{:?}", player.attributes.val), player.money, player.changes.len(), player.game_time), before);
+        assert!(player.items.is_empty());
+        // 账本不进快照：之前的条目还在，这次记下的截掉
+        assert_eq!(player.ledger.entries.len(), 1);
+        assert_eq!(player.ledger.entries[0].after, 20);
+
+        // modify 不管撤销，做到哪算哪
+        assert!(failing.modify(&systems, &mut player).is_err());
+        assert_eq!(player.attributes.get("energy"), Some(&30));
+        assert_eq!(player.money, 5);
+    }
+}
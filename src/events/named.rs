@@ -0,0 +1,136 @@
+// named.rs
+// 具名的条件与修改：在 GameData 顶层的 conditions / modifiers 表里定义一次，
+// 别处用 { type = "Named", name = "..." } / { named = "..." } 引用。
+// 读取数据时就把引用全部展开成定义的内容，运行时不再查表。
+
+use std::collections::HashMap;
+
+use super::{conditions::Condition, modifier::Modifier};
+
+pub struct Definitions<'a> {
+    raw_conditions: &'a HashMap<String, Condition>,
+    raw_modifiers: &'a HashMap<String, Modifier>,
+    pub conditions: HashMap<String, Condition>, // 已展开的
+    pub modifiers: HashMap<String, Modifier>,
+    stack: Vec<String>, // 正在展开的定义，用来查循环引用
+}
+
+impl<'a> Definitions<'a> {
+    pub fn new(conditions: &'a HashMap<String, Condition>, modifiers: &'a HashMap<String, Modifier>) -> Self {
+        Self {
+            raw_conditions: conditions,
+            raw_modifiers: modifiers,
+            conditions: HashMap::new(),
+            modifiers: HashMap::new(),
+            stack: vec![],
+        }
+    }
+
+    /// 展开所有定义（包括没被用到的），返回发现的问题
+    pub fn resolve_all(&mut self) -> Vec<String> {
+        let mut names: Vec<&String> = self.raw_conditions.keys().collect();
+        names.sort();
+        let mut errs: Vec<String> = names.into_iter()
+            .filter_map(|name| self.condition(name).err())
+            .map(|e| format!("具名条件: {e}"))
+            .collect();
+        let mut names: Vec<&String> = self.raw_modifiers.keys().collect();
+        names.sort();
+        errs.extend(names.into_iter()
+            .filter_map(|name| self.modifier(name).err())
+            .map(|e| format!("具名修改: {e}")));
+        errs
+    }
+
+    pub fn condition(&mut self, name: &str) -> Result<Condition, String> {
+        if let Some(cond) = self.conditions.get(name) { return Ok(cond.clone()); }
+        let mut cond = self.raw_conditions.get(name).ok_or(format!("未知具名条件 {name}"))?.clone();
+        self.enter(format!("条件 {name}"))?;
+        let ret = cond.resolve(self);
+        self.stack.pop();
+        ret?;
+        self.conditions.insert(name.to_string(), cond.clone());
+        Ok(cond)
+    }
+
+    pub fn modifier(&mut self, name: &str) -> Result<Modifier, String> {
+        if let Some(modifier) = self.modifiers.get(name) { return Ok(modifier.clone()); }
+        let mut modifier = self.raw_modifiers.get(name).ok_or(format!("未知具名修改 {name}"))?.clone();
+        self.enter(format!("修改 {name}"))?;
+        let ret = modifier.resolve(self);
+        self.stack.pop();
+        ret?;
+        self.modifiers.insert(name.to_string(), modifier.clone());
+        Ok(modifier)
+    }
+
+    fn enter(&mut self, def: String) -> Result<(), String> {
+        if let Some(i) = self.stack.iter().position(|d| d == &def) {
+            return Err(format!("循环引用 {} -> {def}", self.stack[i..].join(" -> ")));
+        }
+        self.stack.push(def);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{events::conditions::Condition, game::GameData, testing};
+
+    fn resolve(extra: &str) -> Result<GameData, String> {
+        let mut data: GameData = toml::from_str(&testing::source(extra)).unwrap();
+        data.resolve().map(|_| data).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn expands_references() {
+        let data = resolve(r#"
+[conditions]
+tired = "energy < 20"
+very_tired = { type = "And", conds = [{ type = "Named", name = "tired" }, "health < 50"] }
+
+[modifiers]
+rest = { attr = "energy", val = { Add = 10 } }
+nap = [{ named = "rest" }, { cond = { type = "Named", name = "very_tired" }, group = [{ named = "rest" }] }]
+"#).unwrap();
+        let Condition::And(group) = &data.conditions["very_tired"] else { panic!() };
+        assert!(matches!(group.conds[0], Condition::Expr(_)));
+        let text = format!("{:?}", data.modifiers["nap"]);
+        assert!(!text.contains("Named"), "{text}");
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let err = resolve(r#"
+[conditions]
+a = { type = "Named", name = "b" }
+b = { type = "Not", cond = { type = "Named", name = "a" } }
+me = { type = "Or", conds = [{ type = "Named", name = "me" }] }
+
+[modifiers]
+x = [{ named = "y" }]
+y = { cond = { type = "Named", name = "a" }, group = [{ named = "x" }] }
+"#).unwrap_err();
+        for cycle in [
+            "具名条件: 循环引用 条件 a -> 条件 b -> 条件 a",
+            "具名条件: 循环引用 条件 me -> 条件 me",
+            "具名修改: 循环引用 修改 x -> 修改 y -> 修改 x",
+        ] {
+            assert!(err.contains(cycle), "{err}");
+        }
+    }
+
+    #[test]
+    fn reports_unknown_names() {
+        let err = resolve(r#"
+[[events]]
+name = "day"
+priority = 1
+force = false
+condition = { type = "Named", name = "nope" }
+segments = [{ name = "start", text = "", options = [{ text = "好", modifier = { named = "missing" } }] }]
+"#).unwrap_err();
+        assert!(err.contains("事件 day: 未知具名条件 nope"), "{err}");
+        assert!(err.contains("事件 day 段落 start 选项 0: 未知具名修改 missing"), "{err}");
+    }
+}
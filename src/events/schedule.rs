@@ -0,0 +1,51 @@
+// schedule.rs
+// 安排在将来某个游戏时间发生的事，比如“三天后出成绩”。
+// 队列存在 Player 上，跟着存档走；到点后由 TimeSystem 放出来。
+
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::game::GameData;
+
+use super::triggers::Trigger;
+
+/// 到点时要做的事
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Pending {
+    Event { event: String },     // 手头没有事件时开始该事件，不再判定事件的条件
+    Trigger { trigger: Trigger }, // 触发一次该触发器
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scheduled {
+    pub at: NaiveDateTime,
+    pub what: Pending,
+}
+
+/// 从现在起过多久
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Delay {
+    #[serde(default)]
+    pub days: i64,
+    #[serde(default)]
+    pub hours: i64,
+    #[serde(default)]
+    pub minutes: i64,
+}
+
+impl Delay {
+    pub fn duration(&self) -> Duration {
+        Duration::days(self.days) + Duration::hours(self.hours) + Duration::minutes(self.minutes)
+    }
+}
+
+impl Pending {
+    pub fn validate(&self, data: &GameData) -> Vec<String> {
+        match self {
+            Pending::Event { event } if !data.events.iter().any(|evt| &evt.name == event) =>
+                vec![format!("未知事件 {event}")],
+            _ => vec![],
+        }
+    }
+}
@@ -0,0 +1,419 @@
+// template.rs
+// 文本模板。段落、选项的文本里可以写占位符，显示的时候换成玩家当前的状态：
+//   {player.name} {player.gender}          主角的名字、性别，见 [protagonist]
+//   {attr.energy} {attr.energy.max}        属性（含装备加成），以及上下限 .max / .min
+//   {item.coffee.count} {item.coffee.name} 背包里的数量、物品显示名
+//   {money} {location.name} {location.displayed_name}
+//   {time} {time.weekday} {time:%H:%M}     时间，冒号后面是 chrono 的格式
+// 还可以按值换说法：
+//   {item.coffee.count|plural:一杯咖啡|# 杯咖啡}   值为 1 用前一个，否则用后一个，# 换成值
+//     后面紧跟字母数字的 # 不换（[color=#ff0000] 这样的标记），字面的 # 也可以写成 ##
+//   {player.gender|select:男=他|女=她|TA}          按值挑，最后不带 = 的是默认
+//   {if: energy < 20 ? 困得睁不开眼 | 精神还行}      条件是表达式（见 expr.rs）
+// 分支里还可以再写占位符。字面的花括号写成 {{ 和 }}。
+// 写错的占位符读取数据时就报出来；运行时仍然对不上的（比如旧存档）原样显示。
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::Datelike;
+
+use crate::{game::GameData, player::Player, systems::{time_system::TimeSystem, Systems}};
+
+use super::expr::Expr;
+
+enum Piece<'a> {
+    Text(&'a str),
+    Slot(&'a str), // 花括号里面的部分
+}
+
+enum Slot<'a> {
+    If { cond: &'a str, then: &'a str, otherwise: &'a str },
+    Value { path: &'a str, form: Option<Form<'a>> },
+}
+
+enum Form<'a> {
+    Plural(&'a str, &'a str),
+    Select(Vec<(Option<&'a str>, &'a str)>), // 没有键的是默认
+}
+
+enum Value {
+    Num(i64),
+    Str(String),
+}
+
+#[derive(PartialEq)]
+enum Kind { Num, Str }
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Num(n) => write!(f, "{n}"),
+            Value::Str(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+/// 把占位符换成玩家当前的状态
+pub fn render(text: &str, systems: &Systems, player: &Player) -> String {
+    if !text.contains(['{', '}']) { return text.to_string(); }
+    let pieces = match pieces(text) {
+        Ok(pieces) => pieces,
+        Err(e) => { eprintln!("ERROR: 文本模板 {text:?}: {e}"); return text.to_string(); },
+    };
+    let mut out = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Text(s) => out.push_str(s),
+            Piece::Slot(body) => match render_slot(body, systems, player) {
+                Ok(s) => out.push_str(&s),
+                Err(e) => {
+                    eprintln!("ERROR: 文本模板 {{{body}}}: {e}");
+                    out.push_str(&format!("{{{body}}}"));
+                },
+            },
+        }
+    }
+    out
+}
+
+/// 读取数据时的检查：语法、占位符是否认得、条件表达式是否写对
+pub fn validate(text: &str, data: &GameData) -> Vec<String> {
+    let pieces = match pieces(text) {
+        Ok(pieces) => pieces,
+        Err(e) => return vec![e],
+    };
+    let mut errs = vec![];
+    for piece in pieces {
+        let Piece::Slot(body) = piece else { continue; };
+        let at = |e: String| format!("{{{body}}}: {e}");
+        match slot(body) {
+            Err(e) => errs.push(at(e)),
+            Ok(Slot::If { cond, then, otherwise }) => {
+                match Expr::parse(cond) {
+                    Ok(expr) => errs.extend(expr.validate(data).into_iter().map(at)),
+                    Err(e) => errs.push(at(e.to_string())),
+                }
+                errs.extend(validate(then, data));
+                errs.extend(validate(otherwise, data));
+            },
+            Ok(Slot::Value { path, form }) => {
+                let kind = match kind(path, data) {
+                    Ok(kind) => kind,
+                    Err(e) => { errs.push(at(e)); continue; },
+                };
+                match form {
+                    Some(Form::Plural(..)) if kind != Kind::Num => errs.push(at("plural 只能用在数值上".into())),
+                    Some(Form::Plural(one, other)) => {
+                        errs.extend(validate(one, data));
+                        errs.extend(validate(other, data));
+                    },
+                    Some(Form::Select(arms)) => {
+                        for (_, arm) in arms { errs.extend(validate(arm, data)); }
+                    },
+                    None => (),
+                }
+            },
+        }
+    }
+    errs
+}
+
+fn render_slot(body: &str, systems: &Systems, player: &Player) -> Result<String, String> {
+    match slot(body)? {
+        Slot::If { cond, then, otherwise } => {
+            let cond = Expr::parse(cond).map_err(|e| e.to_string())?.eval(systems, player)?;
+            Ok(render(if cond.truthy() { then } else { otherwise }, systems, player))
+        },
+        Slot::Value { path, form } => {
+            let value = value(path, systems, player).ok_or(format!("未知占位符 {path}"))?;
+            let arm = match form {
+                None => return Ok(value.to_string()),
+                Some(Form::Plural(one, other)) => match value {
+                    Value::Num(1) => one,
+                    Value::Num(_) => other,
+                    Value::Str(_) => return Err("plural 只能用在数值上".into()),
+                },
+                Some(Form::Select(arms)) => {
+                    let value = value.to_string();
+                    arms.iter().find(|(key, _)| key.is_none_or(|key| key.trim() == value))
+                        .map_or("", |(_, arm)| arm)
+                },
+            };
+            Ok(render(&fill(arm, &value.to_string()), systems, player))
+        },
+    }
+}
+
+/// 把分支里单独的 # 换成值
+fn fill(arm: &str, value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = arm.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('#', Some('#')) => { chars.next(); out.push('#'); },
+            ('#', Some(next)) if next.is_ascii_alphanumeric() => out.push('#'),
+            ('#', _) => out.push_str(value),
+            (c, _) => out.push(c),
+        }
+    }
+    out
+}
+
+fn value(path: &str, systems: &Systems, player: &Player) -> Option<Value> {
+    let time = player.game_time;
+    Some(match path {
+        "player.name" => Value::Str(player.name.clone()),
+        "player.gender" => Value::Str(player.gender.clone()),
+        "money" => Value::Num(player.money as i64),
+        "location" | "location.name" => Value::Str(player.game_map.clone()),
+        "location.displayed_name" => Value::Str(systems.map.maps.get(&player.game_map)
+            .and_then(|map| map.displayed_name.clone())
+            .unwrap_or(player.game_map.clone())),
+        "time" => Value::Str(time.format("%Y-%m-%d %H:%M").to_string()),
+        "time.weekday" => Value::Str(TimeSystem::weekday_name(time.weekday()).to_string()),
+        _ => {
+            if let Some(fmt) = path.strip_prefix("time:") {
+                // 格式写错时 chrono 在输出时才 panic，先检查一遍
+                let items = StrftimeItems::new(fmt).collect::<Vec<_>>();
+                if items.contains(&Item::Error) { return None; }
+                Value::Str(time.format_with_items(items.into_iter()).to_string())
+            } else if let Some(attr) = path.strip_prefix("attr.") {
+                if let Some(val) = player.attribute(systems, attr) { return Some(Value::Num(val as i64)); }
+                let (attr, bound) = attr.rsplit_once('.')?;
+                let def = player.attribute_defs.get(attr)?;
+                match bound {
+                    "max" => Value::Num(def.max as i64),
+                    "min" => Value::Num(def.min as i64),
+                    _ => return None,
+                }
+            } else if let Some(item) = path.strip_prefix("item.") {
+                let (item, field) = item.rsplit_once('.')?;
+                if !systems.item.items.contains_key(item) { return None; }
+                match field {
+                    "count" => Value::Num(player.items.get(item).map_or(0, |(_, num)| *num) as i64),
+                    "name" => Value::Str(systems.item.displayed_name(item).to_string()),
+                    _ => return None,
+                }
+            } else {
+                return None;
+            }
+        },
+    })
+}
+
+/// 和 value 对应，只看数据不看玩家
+fn kind(path: &str, data: &GameData) -> Result<Kind, String> {
+    let unknown = || Err(format!("未知占位符 {path}"));
+    match path {
+        "player.name" | "player.gender" | "location" | "location.name" | "location.displayed_name"
+            | "time" | "time.weekday" => Ok(Kind::Str),
+        "money" => Ok(Kind::Num),
+        _ => {
+            if let Some(fmt) = path.strip_prefix("time:") {
+                if StrftimeItems::new(fmt).any(|item| item == Item::Error) {
+                    return Err(format!("时间格式写错了：{fmt}"));
+                }
+                Ok(Kind::Str)
+            } else if let Some(attr) = path.strip_prefix("attr.") {
+                let known = |attr: &str| data.player.iter().any(|a| a.name == attr);
+                match attr.rsplit_once('.') {
+                    _ if known(attr) => Ok(Kind::Num),
+                    Some((attr, "max" | "min")) if known(attr) => Ok(Kind::Num),
+                    _ => Err(format!("未知属性 {attr}")),
+                }
+            } else if let Some(item) = path.strip_prefix("item.") {
+                match item.rsplit_once('.') {
+                    Some((item, _)) if !data.has_item(item) => Err(format!("未知物品 {item}")),
+                    Some((_, "count")) => Ok(Kind::Num),
+                    Some((_, "name")) => Ok(Kind::Str),
+                    _ => unknown(),
+                }
+            } else {
+                unknown()
+            }
+        },
+    }
+}
+
+fn pieces(text: &str) -> Result<Vec<Piece<'_>>, String> {
+    let mut ret = vec![];
+    let mut rest = text;
+    while let Some(i) = rest.find(['{', '}']) {
+        if i > 0 { ret.push(Piece::Text(&rest[..i])); }
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            ret.push(Piece::Text(&tail[..1]));
+            rest = &tail[2..];
+        } else if tail.starts_with('}') {
+            return Err("多余的 }，字面的花括号写成 }}".into());
+        } else {
+            let end = closing(tail).ok_or("{ 没有配对的 }")?;
+            ret.push(Piece::Slot(&tail[1..end]));
+            rest = &tail[end + 1..];
+        }
+    }
+    if !rest.is_empty() { ret.push(Piece::Text(rest)); }
+    Ok(ret)
+}
+
+/// s 以 { 开头，找与之配对的 }
+fn closing(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 { return Some(i); }
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+/// 按不在花括号里的 sep 切开
+fn split(s: &str, sep: char) -> Vec<&str> {
+    let (mut ret, mut depth, mut start) = (vec![], 0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if c == sep && depth == 0 => {
+                ret.push(&s[start..i]);
+                start = i + c.len_utf8();
+            },
+            _ => (),
+        }
+    }
+    ret.push(&s[start..]);
+    ret
+}
+
+fn slot(body: &str) -> Result<Slot<'_>, String> {
+    if let Some(rest) = body.strip_prefix("if:") {
+        let [cond, arms] = split(rest, '?')[..] else { return Err("条件后面要接 ? 和分支".into()); };
+        let (then, otherwise) = match split(arms, '|')[..] {
+            [then] => (then, ""),
+            [then, otherwise] => (then, otherwise),
+            _ => return Err("条件只能有两个分支".into()),
+        };
+        return Ok(Slot::If { cond: cond.trim(), then: then.trim(), otherwise: otherwise.trim() });
+    }
+    let parts = split(body, '|');
+    let path = parts[0].trim();
+    let Some(first) = parts.get(1) else { return Ok(Slot::Value { path, form: None }); };
+    let (form, first) = first.split_once(':').ok_or(format!("不认识的写法 {first}"))?;
+    let arms: Vec<&str> = std::iter::once(first).chain(parts[2..].iter().copied()).collect();
+    let form = match form.trim() {
+        "plural" => match arms[..] {
+            [one, other] => Form::Plural(one, other),
+            _ => return Err("plural 要两个分支：单数|复数".into()),
+        },
+        "select" => {
+            let arms: Vec<_> = arms.iter().map(|arm| match arm.split_once('=') {
+                Some((key, arm)) => (Some(key), arm),
+                None => (None, *arm),
+            }).collect();
+            if arms.iter().rev().skip(1).any(|(key, _)| key.is_none()) {
+                return Err("select 里只有最后一个分支可以不写值".into());
+            }
+            Form::Select(arms)
+        },
+        form => return Err(format!("不认识的写法 {form}")),
+    };
+    Ok(Slot::Value { path, form: Some(form) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn render_with(text: &str, f: impl FnOnce(&mut Player)) -> String {
+        let (_, systems, mut player) = testing::setup("");
+        f(&mut player);
+        super::render(text, &systems, &player)
+    }
+
+    fn render(text: &str) -> String { render_with(text, |_| ()) }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(render("没有占位符"), "没有占位符");
+        assert_eq!(render("{player.name}（{player.gender}）"), "小科（男）");
+        assert_eq!(render("精力 {attr.energy}/{attr.energy.max}，最低 {attr.energy.min}"), "精力 15/100，最低 0");
+        assert_eq!(render("{item.coffee.name} ×{item.coffee.count}"), "咖啡 ×0");
+        assert_eq!(render("{location} {location.name} {location.displayed_name}"), "Town Town 小镇");
+        assert_eq!(render("{time} {time.weekday} {time:%H点%M分}"), "2024-01-01 00:00 周一 00点00分");
+        assert_eq!(render("{{字面}} {{{money}}}"), "{字面} {0}");
+    }
+
+    #[test]
+    fn forms() {
+        let cups = "{item.coffee.count|plural:一杯咖啡|# 杯咖啡}";
+        assert_eq!(render(cups), "0 杯咖啡");
+        let one = |p: &mut Player| { p.items.insert("coffee".into(), (toml::Value::Table(Default::default()), 1)); };
+        assert_eq!(render_with(cups, one), "一杯咖啡");
+        let pronoun = "{player.gender|select:男=他|女=她|TA}";
+        assert_eq!(render(pronoun), "他");
+        assert_eq!(render_with(pronoun, |p| p.gender = "女".into()), "她");
+        assert_eq!(render_with(pronoun, |p| p.gender = "".into()), "TA");
+        assert_eq!(render("{player.gender|select:女=她}"), "");
+        // 颜色里的 # 不是值
+        let red = "{item.coffee.count|plural:[color=#ff0000]一杯[/color]|[color=#ff0000]#[/color] 杯}";
+        assert_eq!(render(red), "[color=#ff0000]0[/color] 杯");
+        assert_eq!(render_with(red, one), "[color=#ff0000]一杯[/color]");
+        assert_eq!(render("{money|plural:一元|#元，编号 ##1}"), "0元，编号 #1");
+        // 分支里可以再写占位符，也可以嵌套条件
+        assert_eq!(render("{if: energy < 20 ? {player.name}困了 | 精神还行}"), "小科困了");
+        assert_eq!(render_with("{if: energy < 20 ? 困了 | 精神还行}", |p| *p.attributes.get_mut("energy").unwrap() = 50), "精神还行");
+        assert_eq!(render("{if: energy < 20 ? {if: energy < 10 ? 快睡着了 | 困了}}"), "困了");
+        assert_eq!(render("{if: energy > 20 ? 精神还行}"), "");
+    }
+
+    #[test]
+    fn kept_raw_at_runtime() {
+        assert_eq!(render("{attr.nope}，{player.name}"), "{attr.nope}，小科");
+        assert_eq!(render("{item.tea.count}"), "{item.tea.count}");
+        assert_eq!(render("{time:%Q}"), "{time:%Q}");
+        assert_eq!(render("{player.name|plural:a|b}"), "{player.name|plural:a|b}");
+        assert_eq!(render("少了 {player.name"), "少了 {player.name");
+        assert_eq!(render("多了 }"), "多了 }");
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(pieces("a {b").err(), Some("{ 没有配对的 }".into()));
+        assert_eq!(pieces("a } b").err(), Some("多余的 }，字面的花括号写成 }}".into()));
+        assert_eq!(pieces("{a{b}").err(), Some("{ 没有配对的 }".into()));
+        assert!(pieces("{a{b}c} }}").is_ok());
+        assert_eq!(split("a|{b|c}|d", '|'), ["a", "{b|c}", "d"]);
+        assert!(slot("if: x").is_err());
+        assert!(slot("if: x ? a | b | c").is_err());
+        assert!(slot("money|plural:一").is_err());
+        assert!(slot("money|plural:一|二|三").is_err());
+        assert!(slot("money|what:x").is_err());
+        assert!(slot("money|plain").is_err());
+        assert!(slot("player.gender|select:他|女=她").is_err());
+    }
+
+    #[test]
+    fn validation() {
+        let data = testing::data("");
+        let errs = |text: &str| validate(text, &data);
+        assert!(errs("{player.name} {attr.energy.max} {item.coffee.count|plural:一杯|# 杯} {time:%H:%M}").is_empty());
+        assert!(errs("{if: energy < 20 && has('coffee') ? 困了 | {attr.energy}}").is_empty());
+        assert_eq!(errs("{attr.nope}"), ["{attr.nope}: 未知属性 nope"]);
+        assert_eq!(errs("{attr.energy.avg}"), ["{attr.energy.avg}: 未知属性 energy.avg"]);
+        assert_eq!(errs("{item.tea.count}"), ["{item.tea.count}: 未知物品 tea"]);
+        assert_eq!(errs("{item.coffee.price}"), ["{item.coffee.price}: 未知占位符 item.coffee.price"]);
+        assert_eq!(errs("{time:%Q}"), ["{time:%Q}: 时间格式写错了：%Q"]);
+        assert_eq!(errs("{player.name|plural:一|二}"), ["{player.name|plural:一|二}: plural 只能用在数值上"]);
+        assert_eq!(errs("{if: nope > 1 ? a | b}"), ["{if: nope > 1 ? a | b}: 表达式中的未知变量 nope"]);
+        assert_eq!(errs("{if: 1 + ? a}").len(), 1);
+        // 分支里的错误也要报
+        assert_eq!(errs("{if: true ? {attr.nope} | b}"), ["{attr.nope}: 未知属性 nope"]);
+        assert_eq!(errs("{money|select:1={item.tea.name}|x}"), ["{item.tea.name}: 未知物品 tea"]);
+        assert_eq!(errs("a {b"), ["{ 没有配对的 }"]);
+    }
+}
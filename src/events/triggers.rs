@@ -1,6 +1,6 @@
 // triggers.rs
 
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
 
 use crate::player::Player;
 use crate::systems::Systems;
@@ -37,6 +37,8 @@ pub enum Trigger {
 
     Always,
     Init,
+    NewDay,  // 时间跨过了午夜
+    NewWeek, // 时间跨进了新的一周（周一）
     PreInit,
     Custom(String),
 }
@@ -66,7 +68,6 @@ impl TriggerSystem {
         player: &Player,
         systems: &Systems
     ) -> Option<String> {
-        let evt = self.get_all_events(&player.trigger);
         // 我觉得可以分成持久化与非持久化的trigger，这样更合理。
         // …… 不对。Trigger是用的相当保守的，所以被称为Trigger。
         // 但是我们仍然需要删除trigger。这是肯定的。如果持续触发，就持续添加……什么怪主意
@@ -75,8 +76,14 @@ impl TriggerSystem {
                 |evt| (evt.priority,evt.force)
             )).unwrap_or(Some((0,false))).unwrap();
         if cur_force { return None; } // 如果硬要执行，我们也不好阻止。
+        // 按名字排好再判定：条件里可能用到随机数，顺序固定了同一个种子才能重放
+        // 每轮都要走一遍，只借用名字，不复制
+        let evt: BTreeSet<&String> = player.trigger.iter()
+            .filter_map(|k| self.registed_event.get(k))
+            .flatten()
+            .collect();
         let mut heap = BinaryHeap::new();
-        for event in evt.iter().filter_map(|k| systems.event.events.get(k)) {
+        for event in evt.into_iter().filter_map(|k| systems.event.events.get(k)) {
             // 检查事件条件
             if event.condition.is_met(systems, player)
               && (cur_priority == 0 || event.priority > cur_priority) {
@@ -86,6 +93,17 @@ impl TriggerSystem {
         heap.pop().map(|evt| evt.name.clone())
     }
 
+    /// 立即触发（见 Modifier::Trigger）：登记在这个触发器上、条件满足的事件里挑优先级最高的。
+    /// 是当前事件自己要触发的，所以不和它比优先级
+    pub fn fire(&self, trigger: &Trigger, systems: &Systems, player: &Player) -> Option<String> {
+        let evt: BTreeSet<&String> = self.registed_event.get(trigger).into_iter().flatten().collect();
+        let mut heap: BinaryHeap<_> = evt.into_iter()
+            .filter_map(|k| systems.event.events.get(k))
+            .filter(|event| event.condition.is_met(systems, player))
+            .collect();
+        heap.pop().map(|evt| evt.name.clone())
+    }
+
     // pub fn check(
     //     &mut self,
     //     triggers: &HashSet<Trigger>,
@@ -7,6 +7,8 @@ pub struct Assets {
     pub avatar: HashMap<String,ImageData>,
     #[serde(default)]
     pub avatar_deco: HashMap<String,ImageData>,
+    #[serde(default)]
+    pub item_icon: HashMap<String,ImageData>,
 }
 
 #[derive(Default, Deserialize, Clone,Debug)]
@@ -4,7 +4,10 @@ use std::{
 };
 
 use crate::{
-    game::{DataSource, GameData, GameErr}, player::Attribute, frontend::assets::ImageData
+    events::changes::Change,
+    game::{DataSource, GameData, GameErr}, player::{Attribute, Player}, frontend::assets::ImageData,
+    ledger::{self, LedgerEntry},
+    systems::{craft_system::CraftSystem, shop_system::ShopSystem, Systems},
 };
 
 use super::assets::Assets;
@@ -13,13 +16,42 @@ use super::assets::Assets;
 #[derive(Clone, Default, Debug)]
 pub struct ToFrontend {
     pub main_area: Option<String>,
-    pub option_area: Option<Vec<(String,bool)>>,
+    pub option_area: Option<Vec<(String,bool,Option<String>)>>, // 文本、是否可选、不可选的原因
     pub option_display_disabled: Option<bool>,
     pub player_status: Option<Vec<String>>,
 
     pub player_attribute: Option<Vec<(String, i32, i32)>>,
     pub avatar_image: (Option<ImageData>,Option<Vec<ImageData>>),
+    pub inventory: Option<Vec<InventoryItem>>,
+    pub money: Option<(String, i32)>, // 货币名与余额
+    pub shops: Option<Vec<ShopView>>, // 当前能逛的商店
+    pub recipes: Option<Vec<RecipeView>>, // 会的配方
     pub debug: Option<DebugToFrontend>,
+    pub error: Option<String>, // 上一步出的错，只显示一次
+    pub changes: Option<Vec<ChangeView>>, // 刚刚发生的变化，前端弹出提示
+    pub ledger: Option<(bool, Vec<LedgerEntry>)>, // 账本的新条目；为真时是从头发的，替换掉旧的
+    pub icons: Option<HashMap<String, ImageData>>, // 文本里 [icon=…] 用的图标，开局时发一次
+}
+
+/// 一条变化提示，名字都已经查好了
+#[derive(Clone, Debug)]
+pub struct ChangeView {
+    pub change: Change,
+    pub text: String,
+}
+
+/// 背包中的一格，已经把定义与资源都查好了，前端直接画就行
+#[derive(Clone, Default, Debug)]
+pub struct InventoryItem {
+    pub id: String,
+    pub name: String,
+    pub count: usize,
+    pub icon: Option<ImageData>,
+    pub description: String,
+    pub category: String,
+    pub tags: Vec<String>,
+    pub usage: Option<(String,bool)>, // 使用按钮的文本与是否可用；None 为不可使用
+    pub equipped: Option<String>,     // 装备在哪个栏位上
 }
 
 impl ToFrontend {
@@ -34,7 +66,24 @@ impl ToFrontend {
         }
         if let Some(avatar_image) = target.avatar_image.0 { self.avatar_image.0 = Some(avatar_image); }
         if let Some(avatar_image) = target.avatar_image.1 { self.avatar_image.1 = Some(avatar_image); }
+        if let Some(inventory) = target.inventory { self.inventory = Some(inventory); }
+        if let Some(money) = target.money { self.money = Some(money); }
+        if let Some(shops) = target.shops { self.shops = Some(shops); }
+        if let Some(recipes) = target.recipes { self.recipes = Some(recipes); }
         if let Some(debug) = target.debug { self.debug = Some(debug); }
+        if let Some(icons) = target.icons { self.icons = Some(icons); }
+        self.error = target.error; // 出错提示不保留到下一次
+        if let Some(changes) = target.changes { self.changes.get_or_insert(vec![]).extend(changes); }
+        match (target.ledger, &mut self.ledger) {
+            (Some((false, entries)), Some((_, ledger))) => {
+                ledger.extend(entries);
+                // 和后端一样只留最近的
+                let over = ledger.len().saturating_sub(ledger::MAX_ENTRIES);
+                ledger.drain(..over);
+            },
+            (Some(ledger), _) => self.ledger = Some(ledger),
+            (None, _) => (),
+        }
     }
 }
 
@@ -46,24 +95,55 @@ pub struct Frontend {
 }
 
 #[derive(Clone, Default, Debug)]
-pub struct DebugToFrontend;
+pub struct ShopView {
+    pub id: String,
+    pub name: String,
+    pub goods: Vec<ShopGoods>,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct ShopGoods {
+    pub item: String,
+    pub name: String,
+    pub price: i32,
+    pub stock: Option<usize>,    // 剩余库存，None 为不限
+    pub sell_price: Option<i32>, // 回收价，None 为不回收
+    pub owned: usize,            // 玩家手上有几个，卖的时候用
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct RecipeView {
+    pub id: String,
+    pub name: String,
+    pub ingredients: Vec<(String, usize, usize)>, // 名称、需要、现有
+    pub results: Vec<(String, usize)>,
+    pub unavailable: Option<String>,              // 现在做不了的原因
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct DebugToFrontend {
+    pub seed: Option<u64>, // 当前这局的随机种子
+}
 
 #[derive(Clone, Default, Debug)]
 pub enum FromFrontend {
     Choice(usize),
+    UseItem(String),
+    Buy { shop: String, item: String, count: usize },
+    Sell { shop: String, item: String, count: usize },
+    Craft(String),
     Debug(DebugFromFrontend),
     #[default]
     None,
 }
 
 impl FromFrontend {
-    pub fn into_choice(self) -> Result<usize, DebugFromFrontend> {
-        if let FromFrontend::Choice(u) = self {
-            Ok(u)
-        } else if let FromFrontend::Debug(dbg) = self {
-            Err(dbg)
-        } else {
-            Err(DebugFromFrontend::default())
+    /// 选择、物品使用、买卖、合成交给事件处理，其他的都视为调试信号
+    pub fn into_input(self) -> Result<Self, DebugFromFrontend> {
+        match self {
+            FromFrontend::Debug(dbg) => Err(dbg),
+            FromFrontend::None => Err(DebugFromFrontend::default()),
+            _ => Ok(self),
         }
     }
 }
@@ -77,22 +157,24 @@ pub struct DebugFromFrontend {
 pub enum DebugSign {
     ReloadData(DataSource<GameData>),
     SetAttribute(String, i32),
+    SetSeed(u64),
     #[default]
     None,
 }
 
 impl Frontend {
-    pub fn display_options(&mut self, options: &[(String,bool)], display_disabled: bool) -> Result<usize, GameErr> {
+    /// 返回 Choice 或者其他玩家操作（UseItem、Buy、Sell、Craft）
+    pub fn display_options(&mut self, options: &[(String,bool,Option<String>)], display_disabled: bool) -> Result<FromFrontend, GameErr> {
         self.cache.display_options(options,display_disabled);
         self.sender.send(self.cache.clone_and_clear())?;
-        Ok(self.receiver.recv()?.into_choice()?)
+        Ok(self.receiver.recv()?.into_input()?)
     }
 
-    pub fn display_all_options(&mut self, options: &[String]) -> Result<usize, GameErr> {
+    pub fn display_all_options(&mut self, options: &[String]) -> Result<FromFrontend, GameErr> {
         self.cache.display_options(
-            &options.iter().map(|s|(s.clone(),false)).collect::<Vec<_>>(),false);
+            &options.iter().map(|s|(s.clone(),false,None)).collect::<Vec<_>>(),false);
         self.sender.send(self.cache.clone_and_clear())?;
-        Ok(self.receiver.recv()?.into_choice()?)
+        Ok(self.receiver.recv()?.into_input()?)
     }
 
     pub fn change_avatar(&mut self, avatar: &String) {
@@ -112,6 +194,98 @@ impl Frontend {
             self.assets.avatar_deco[deco].clone()
         );
     }
+
+    /// 取走玩家身上还没报告的变化，写成提示。看不见的属性不提示
+    pub fn display_changes(&mut self, player: &mut Player, systems: &Systems) {
+        let views = player.changes.drain(..).filter_map(|change| {
+            let text = match &change {
+                Change::Attribute { attr, .. } if player.attribute_defs.get(attr).is_some_and(|def| def.invisible) =>
+                    return None,
+                Change::Attribute { attr, delta } => format!("{attr} {delta:+}"),
+                Change::Item { item, delta } if *delta > 0 => format!("获得 {} ×{delta}", systems.item.displayed_name(item)),
+                Change::Item { item, delta } => format!("失去 {} ×{}", systems.item.displayed_name(item), -delta),
+                Change::Money { delta } => format!("{} {delta:+}", systems.shop.currency.name),
+                Change::Location { to } => format!("来到 {}", systems.map.maps.get(to)
+                    .and_then(|map| map.displayed_name.as_deref())
+                    .unwrap_or(to)),
+                Change::Time { minutes } if minutes % 60 == 0 => format!("过去了 {} 小时", minutes / 60),
+                Change::Time { minutes } if *minutes > 60 => format!("过去了 {} 小时 {} 分钟", minutes / 60, minutes % 60),
+                Change::Time { minutes } => format!("过去了 {minutes} 分钟"),
+            };
+            Some(ChangeView { change, text })
+        });
+        self.cache.changes.get_or_insert(vec![]).extend(views);
+    }
+
+    pub fn display_ledger(&mut self, player: &mut Player) {
+        let Some((from_start, entries)) = player.ledger.unsent() else { return; };
+        match &mut self.cache.ledger {
+            Some((_, ledger)) if !from_start => ledger.extend(entries),
+            ledger => *ledger = Some((from_start, entries)),
+        }
+    }
+
+    pub fn display_inventory(&mut self, player: &Player, systems: &Systems) {
+        let item_sys = &systems.item;
+        let inventory = player.rng.preview(|| player.items.iter().map(|(id, (_, num))| {
+            let def = item_sys.items.get(id);
+            InventoryItem {
+                id: id.clone(),
+                name: item_sys.displayed_name(id).to_string(),
+                count: *num,
+                icon: def.and_then(|def| def.icon.as_ref())
+                    .and_then(|icon| self.assets.item_icon.get(icon))
+                    .cloned(),
+                description: def.map(|def| def.description.clone()).unwrap_or_default(),
+                category: def.map(|def| def.category.clone()).unwrap_or_default(),
+                tags: def.map(|def| def.tags.clone()).unwrap_or_default(),
+                usage: def.and_then(|def| def.usage.as_ref()).map(|usage| (
+                    usage.text.clone().unwrap_or("使用".into()),
+                    usage.condition.as_ref().is_none_or(|c| c.is_met(systems, player)),
+                )),
+                equipped: player.equipment.iter()
+                    .find(|(_, equipped)| *equipped == id)
+                    .map(|(slot, _)| slot.clone()),
+            }
+        }).collect());
+        self.cache.inventory = Some(inventory);
+    }
+
+    /// 余额与当前能逛的商店。营业时间和位置随时在变，所以每次等待输入前都发一次
+    pub fn display_shops(&mut self, player: &Player, systems: &Systems) {
+        self.cache.money = Some((systems.shop.currency.name.clone(), player.money));
+        let shops = player.rng.preview(|| ShopSystem::open_shops(systems, player).map(|shop| ShopView {
+            id: shop.name.clone(),
+            name: systems.shop.displayed_name(&shop.name).to_string(),
+            goods: shop.goods.iter().map(|goods| ShopGoods {
+                item: goods.item.clone(),
+                name: systems.item.displayed_name(&goods.item).to_string(),
+                price: goods.price,
+                stock: ShopSystem::stock_left(player, shop, goods),
+                sell_price: goods.sell_price,
+                owned: player.items.get(&goods.item).map_or(0, |(_, num)| *num),
+            }).collect(),
+        }).collect());
+        self.cache.shops = Some(shops);
+    }
+
+    pub fn display_recipes(&mut self, player: &Player, systems: &Systems) {
+        let mut recipes: Vec<_> = player.rng.preview(|| systems.craft.known(player).map(|recipe| RecipeView {
+            id: recipe.name.clone(),
+            name: systems.craft.displayed_name(&recipe.name).to_string(),
+            ingredients: recipe.ingredients.iter().map(|(item, need)| (
+                systems.item.displayed_name(item).to_string(),
+                *need,
+                player.items.get(item).map_or(0, |(_, num)| *num),
+            )).collect(),
+            results: recipe.results.iter()
+                .map(|result| (systems.item.displayed_name(&result.item).to_string(), result.count))
+                .collect(),
+            unavailable: CraftSystem::can_craft(systems, player, &recipe.name).err().map(|e| e.to_string()),
+        }).collect());
+        recipes.sort_by(|a, b| a.name.cmp(&b.name));
+        self.cache.recipes = Some(recipes);
+    }
 }
 
 impl ToFrontend {
@@ -128,10 +302,11 @@ impl ToFrontend {
     /// 显示选项并获取玩家的选择
     /// 返回玩家选择的选项索引
     /// Blocking => ?
-    pub fn display_options(&mut self, options: &[(String,bool)], display_disabled: bool) {
+    pub fn display_options(&mut self, options: &[(String,bool,Option<String>)], display_disabled: bool) {
+        let option_area = self.option_area.get_or_insert(vec![]); // 没有选项时也要清掉前端上的旧选项
         options
             .iter()
-            .for_each(|opt| self.option_area.get_or_insert(vec![]).push(opt.clone()));
+            .for_each(|opt| option_area.push(opt.clone()));
         self.option_display_disabled = Some(display_disabled);
     }
 
@@ -159,6 +334,10 @@ impl ToFrontend {
     /// 显示错误信息
     pub fn display_error(&mut self, message: &str) {
         println!("ERROR: {message}");
+        match &mut self.error {
+            Some(error) => { error.push('\n'); error.push_str(message); },
+            None => self.error = Some(message.to_string()),
+        }
     }
 
     pub fn change_avatar(&mut self, avatar: ImageData) {
@@ -0,0 +1,294 @@
+// markup.rs
+// 事件文本里的轻量标记，BBCode 的一个子集：
+//   [b]粗体[/b] [i]斜体[/i] [u]下划线[/u] [s]删除线[/s]
+//   [color=red]……[/color] [color=#ff8800]……[/color]
+//   [speaker]小科[/speaker]      说话人，同一个名字总是同一种颜色
+//   [icon=coffee]               行内图标，名字即 [assets.item_icon] 里的键
+//   [ruby=xiǎo kē]小科[/ruby]     注音，小字标在后面偏上
+//   [url=https://……]文字[/url]
+// 字面的 [ 写成 [[。运行时认不出的标签原样显示，读取数据时由 validate 报出来。
+// 这里只负责解析，怎么画由前端决定：egui 前端拼成 LayoutJob（见 rich_text.rs），画不了格式的地方用 plain()。
+
+use super::assets::Assets;
+
+const COLORS: [(&str, (u8, u8, u8)); 9] = [
+    ("red", (230, 80, 80)),
+    ("green", (100, 200, 100)),
+    ("blue", (100, 150, 240)),
+    ("yellow", (230, 200, 60)),
+    ("orange", (240, 150, 50)),
+    ("purple", (180, 120, 230)),
+    ("gray", (150, 150, 150)),
+    ("white", (240, 240, 240)),
+    ("black", (20, 20, 20)),
+];
+
+// 说话人的颜色按名字挑，避开太暗太亮的
+const SPEAKER_COLORS: [(u8, u8, u8); 6] = [
+    (230, 120, 100), (100, 180, 230), (120, 200, 120), (220, 170, 80), (190, 130, 220), (90, 200, 190),
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Style {
+    pub bold: bool,
+    pub italics: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub color: Option<(u8, u8, u8)>,
+    pub speaker: bool,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Span {
+    Text(String, Style),
+    Ruby(String, Style), // 注音，跟在被注的字后面
+    Icon(String),
+}
+
+enum Tag {
+    Bold,
+    Italics,
+    Underline,
+    Strikethrough,
+    Color((u8, u8, u8)),
+    Speaker,
+    Url(String),
+    Ruby(String),
+}
+
+impl Tag {
+    fn parse(body: &str) -> Result<Self, String> {
+        let (name, arg) = match body.split_once('=') {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (body, None),
+        };
+        Ok(match (name.trim(), arg) {
+            ("b", None) => Tag::Bold,
+            ("i", None) => Tag::Italics,
+            ("u", None) => Tag::Underline,
+            ("s", None) => Tag::Strikethrough,
+            ("speaker", None) => Tag::Speaker,
+            ("color", Some(color)) => Tag::Color(parse_color(color).ok_or(format!("不认识的颜色 {color}"))?),
+            ("url", Some(url)) => Tag::Url(url.to_string()),
+            ("ruby", Some(ruby)) => Tag::Ruby(ruby.to_string()),
+            _ => return Err(format!("不认识的标签 [{body}]")),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Tag::Bold => "b",
+            Tag::Italics => "i",
+            Tag::Underline => "u",
+            Tag::Strikethrough => "s",
+            Tag::Color(_) => "color",
+            Tag::Speaker => "speaker",
+            Tag::Url(_) => "url",
+            Tag::Ruby(_) => "ruby",
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    if let Some(hex) = color.strip_prefix('#') {
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|s| u8::from_str_radix(s, 16).ok());
+        return if hex.len() == 6 { Some((channel(0)?, channel(2)?, channel(4)?)) } else { None };
+    }
+    COLORS.iter().find(|(name, _)| *name == color).map(|(_, rgb)| *rgb)
+}
+
+pub fn speaker_color(name: &str) -> (u8, u8, u8) {
+    let hash = name.chars().fold(0u32, |h, c| h.wrapping_mul(31).wrapping_add(c as u32));
+    SPEAKER_COLORS[hash as usize % SPEAKER_COLORS.len()]
+}
+
+fn style(stack: &[Tag]) -> Style {
+    let mut style = Style::default();
+    for tag in stack {
+        match tag {
+            Tag::Bold => style.bold = true,
+            Tag::Italics => style.italics = true,
+            Tag::Underline => style.underline = true,
+            Tag::Strikethrough => style.strikethrough = true,
+            Tag::Color(color) => style.color = Some(*color),
+            Tag::Speaker => style.speaker = true,
+            Tag::Url(url) => style.link = Some(url.clone()),
+            Tag::Ruby(_) => (),
+        }
+    }
+    style
+}
+
+/// 解析。第二项是遇到的问题，运行时不管，读取数据时报出来
+fn parse_checked(text: &str) -> (Vec<Span>, Vec<String>) {
+    let (mut spans, mut errs, mut stack): (Vec<Span>, Vec<String>, Vec<Tag>) = (vec![], vec![], vec![]);
+    let mut buf = String::new();
+    let flush = |buf: &mut String, spans: &mut Vec<Span>, stack: &[Tag]| {
+        if !buf.is_empty() { spans.push(Span::Text(std::mem::take(buf), style(stack))); }
+    };
+    let close = |tag: Tag, spans: &mut Vec<Span>, stack: &[Tag]| {
+        if let Tag::Ruby(ruby) = tag { spans.push(Span::Ruby(ruby, style(stack))); }
+    };
+
+    let mut rest = text;
+    while let Some(i) = rest.find('[') {
+        buf.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if let Some(after) = tail.strip_prefix("[[") {
+            buf.push('[');
+            rest = after;
+            continue;
+        }
+        let Some(end) = tail.find(']') else {
+            errs.push("[ 没有配对的 ]，字面的 [ 写成 [[".into());
+            rest = tail;
+            break;
+        };
+        let body = &tail[1..end];
+        rest = &tail[end + 1..];
+        if let Some(name) = body.strip_prefix('/') {
+            match stack.iter().rposition(|tag| tag.name() == name.trim()) {
+                Some(at) => {
+                    flush(&mut buf, &mut spans, &stack);
+                    // 中间没关的一并关掉
+                    for tag in stack.drain(at..).rev().collect::<Vec<_>>() {
+                        if tag.name() != name.trim() { errs.push(format!("[{}] 没有结束标签", tag.name())); }
+                        close(tag, &mut spans, &stack);
+                    }
+                },
+                None => {
+                    errs.push(format!("[/{name}] 前面没有对应的开始标签"));
+                    buf.push_str(&tail[..end + 1]);
+                },
+            }
+        } else if let Some(icon) = body.strip_prefix("icon=") {
+            flush(&mut buf, &mut spans, &stack);
+            spans.push(Span::Icon(icon.trim().to_string()));
+        } else {
+            match Tag::parse(body) {
+                Ok(tag) => {
+                    flush(&mut buf, &mut spans, &stack);
+                    stack.push(tag);
+                },
+                Err(e) => {
+                    errs.push(e);
+                    buf.push_str(&tail[..end + 1]);
+                },
+            }
+        }
+    }
+    buf.push_str(rest);
+    flush(&mut buf, &mut spans, &stack);
+    while let Some(tag) = stack.pop() {
+        errs.push(format!("[{}] 没有结束标签", tag.name()));
+        close(tag, &mut spans, &stack);
+    }
+    (spans, errs)
+}
+
+pub fn parse(text: &str) -> Vec<Span> {
+    parse_checked(text).0
+}
+
+/// 读取数据时的检查：标签是否认得、是否配对，图标有没有
+pub fn validate(text: &str, assets: &Assets) -> Vec<String> {
+    let (spans, mut errs) = parse_checked(text);
+    for span in spans {
+        if let Span::Icon(icon) = span {
+            if !assets.item_icon.contains_key(&icon) { errs.push(format!("未知图标 {icon}")); }
+        }
+    }
+    errs
+}
+
+/// 去掉标记只留文字，给画不了格式的地方用。注音放进括号，图标略去
+pub fn plain(text: &str) -> String {
+    if !text.contains('[') { return text.to_string(); }
+    parse(text).into_iter().map(|span| match span {
+        Span::Text(text, _) => text,
+        Span::Ruby(ruby, _) => format!("（{ruby}）"),
+        Span::Icon(_) => String::new(),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str, style: Style) -> Span { Span::Text(s.into(), style) }
+
+    fn errs(s: &str) -> Vec<String> { parse_checked(s).1 }
+
+    #[test]
+    fn styles() {
+        let bold = Style { bold: true, ..Default::default() };
+        assert_eq!(parse("普通"), [text("普通", Style::default())]);
+        assert_eq!(parse("a[b]粗[i]斜[/i][/b]c"), [
+            text("a", Style::default()),
+            text("粗", bold.clone()),
+            text("斜", Style { italics: true, ..bold }),
+            text("c", Style::default()),
+        ]);
+        assert_eq!(parse("[u][s]x[/s][/u]"), [text("x", Style { underline: true, strikethrough: true, ..Default::default() })]);
+        assert_eq!(parse("[color=red]红[/color][color=#FF8800]橙[/color]"), [
+            text("红", Style { color: Some((230, 80, 80)), ..Default::default() }),
+            text("橙", Style { color: Some((255, 136, 0)), ..Default::default() }),
+        ]);
+        // 内层颜色覆盖外层
+        assert_eq!(parse("[color=red][color=blue]蓝[/color][/color]"),
+            [text("蓝", Style { color: Some((100, 150, 240)), ..Default::default() })]);
+        assert_eq!(parse("[speaker]小科[/speaker]：走吧"), [
+            text("小科", Style { speaker: true, ..Default::default() }),
+            text("：走吧", Style::default()),
+        ]);
+        assert_eq!(parse("[url=https://example.com]官网[/url]"),
+            [text("官网", Style { link: Some("https://example.com".into()), ..Default::default() })]);
+        assert_eq!(speaker_color("小科"), speaker_color("小科"));
+    }
+
+    #[test]
+    fn ruby_and_icons() {
+        assert_eq!(parse("[ruby=xiǎo kē]小科[/ruby]来了"), [
+            text("小科", Style::default()),
+            Span::Ruby("xiǎo kē".into(), Style::default()),
+            text("来了", Style::default()),
+        ]);
+        assert_eq!(parse("喝[icon=coffee]咖啡"), [
+            text("喝", Style::default()),
+            Span::Icon("coffee".into()),
+            text("咖啡", Style::default()),
+        ]);
+        assert_eq!(plain("[b]喝[/b][icon=coffee][ruby=kā fēi]咖啡[/ruby]"), "喝咖啡（kā fēi）");
+    }
+
+    #[test]
+    fn escapes_and_bad_tags() {
+        assert_eq!(plain("[[b]] 不是标签"), "[b]] 不是标签");
+        assert_eq!(plain("数组 a[[0]"), "数组 a[0]");
+        // 认不出的原样显示
+        assert_eq!(plain("[x]不认识[/x]"), "[x]不认识[/x]");
+        assert_eq!(plain("[color=chartreuse]绿[/color]"), "[color=chartreuse]绿[/color]");
+        assert_eq!(plain("没结束 [b"), "没结束 [b");
+        assert_eq!(plain("[b]没关"), "没关");
+        assert_eq!(parse("[b]没关"), [text("没关", Style { bold: true, ..Default::default() })]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(errs("[b]好[/b][[ [icon=coffee]").is_empty());
+        assert_eq!(errs("[x]"), ["不认识的标签 [x]"]);
+        assert_eq!(errs("[b=1]"), ["不认识的标签 [b=1]"]);
+        assert_eq!(errs("[color=#12345]a[/color]"), ["不认识的颜色 #12345", "[/color] 前面没有对应的开始标签"]);
+        assert_eq!(errs("[url]a[/url]"), ["不认识的标签 [url]", "[/url] 前面没有对应的开始标签"]);
+        assert_eq!(errs("a[/b]"), ["[/b] 前面没有对应的开始标签"]);
+        assert_eq!(errs("[b]a"), ["[b] 没有结束标签"]);
+        assert_eq!(errs("[b][i]a[/b]"), ["[i] 没有结束标签"]);
+        assert_eq!(errs("a [b"), ["[ 没有配对的 ]，字面的 [ 写成 [["]);
+
+        let mut assets = Assets::default();
+        assets.item_icon.insert("coffee".into(), Default::default());
+        assert!(validate("[icon=coffee]", &assets).is_empty());
+        assert_eq!(validate("[icon=tea][b]", &assets), ["[b] 没有结束标签", "未知图标 tea"]);
+    }
+}
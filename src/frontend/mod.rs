@@ -1,4 +1,5 @@
 pub mod frontend;
 pub mod assets;
+pub mod markup;
 
 pub use frontend::*;
\ No newline at end of file
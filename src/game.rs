@@ -4,16 +4,23 @@ use std::{
 
 use crate::{
     events::{
-        events::{EventData, EventSystem},
+        conditions::Condition,
+        events::EventData,
+        modifier::Modifier,
+        named::Definitions,
+        template,
         triggers::{Trigger, TriggerSystem},
     },
-    frontend::{assets::Assets, DebugFromFrontend, FromFrontend, Frontend, ToFrontend},
-    player::{Attribute, Player},
+    frontend::{assets::Assets, markup, DebugFromFrontend, DebugToFrontend, FromFrontend, Frontend, ToFrontend},
+    ledger::Source,
+    player::{Attribute, Player, Protagonist},
+    rng::GameRng,
     systems::{
-        map_system::{Map, MapSystem}, time_system::TimeSystem, Systems
+        craft_system::Recipe, item_system::ItemDef, map_system::Map,
+        script_system::{Script, ScriptConfig, ScriptSystem}, shop_system::{Currency, ShopDef}, time_system::Calendar, Systems
     },
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::Deserialize;
 
 #[derive(Deserialize, Clone, Debug, Default)]
@@ -22,9 +29,197 @@ pub struct GameData {
     pub events: Vec<EventData>,
     pub player: Vec<Attribute>, // 修改为 Vec<Attribute>
     #[serde(default)]
+    pub protagonist: Protagonist,
+    #[serde(default)]
     pub assets: Assets,
     #[serde(default)]
     pub trigger: Vec<HashMap<String,Trigger>>,
+    #[serde(default)]
+    pub items: Vec<ItemDef>,
+    pub inventory_capacity: Option<usize>,
+    #[serde(default)]
+    pub currency: Currency,
+    #[serde(default)]
+    pub shops: Vec<ShopDef>,
+    #[serde(default)]
+    pub recipes: Vec<Recipe>,
+    #[serde(default)]
+    pub script: ScriptConfig,
+    #[serde(default)]
+    pub calendar: Calendar,
+    #[serde(default)]
+    pub conditions: HashMap<String, Condition>, // 具名条件，见 named.rs
+    #[serde(default)]
+    pub modifiers: HashMap<String, Modifier>,   // 具名修改
+}
+
+impl GameData {
+    pub fn has_item(&self, id: &str) -> bool {
+        self.items.iter().any(|item| item.name == id)
+    }
+
+    pub fn has_recipe(&self, id: &str) -> bool {
+        self.recipes.iter().any(|recipe| recipe.name == id)
+    }
+
+    /// 依次交出数据里所有的条件与修改，连同出处（报错用）
+    fn visit(&mut self, f: &mut dyn FnMut(Visited, &dyn Fn() -> String)) {
+        for evt in &mut self.events {
+            f(Visited::Condition(&mut evt.condition), &|| format!("事件 {}", evt.name));
+            for seg in &mut evt.segments {
+                for (i, opt) in seg.options.iter_mut().enumerate() {
+                    let at = || format!("事件 {} 段落 {} 选项 {}", evt.name, seg.name, i);
+                    if let Some(cond) = &mut opt.condition { f(Visited::Condition(cond), &at); }
+                    f(Visited::Modifier(&mut opt.modifier), &at);
+                }
+            }
+        }
+        for item in &mut self.items {
+            let Some(usage) = &mut item.usage else { continue; };
+            let at = || format!("物品 {} 的使用", item.name);
+            if let Some(cond) = &mut usage.condition { f(Visited::Condition(cond), &at); }
+            f(Visited::Modifier(&mut usage.modifier), &at);
+        }
+        for map in &mut self.maps {
+            for conn in &mut map.connections {
+                let Some(cond) = &mut conn.condition else { continue; };
+                f(Visited::Condition(cond), &|| format!("地图 {} -> {}", map.name, conn.to));
+            }
+        }
+    }
+
+    /// 展开所有具名条件、具名修改的引用，并检查有没有循环引用。和 validate 一样，问题一次性全部报出来
+    pub fn resolve(&mut self) -> Result<()> {
+        let (conditions, modifiers) = (std::mem::take(&mut self.conditions), std::mem::take(&mut self.modifiers));
+        let mut defs = Definitions::new(&conditions, &modifiers);
+        let mut errs = defs.resolve_all();
+        self.visit(&mut |visited, at| {
+            let res = match visited {
+                Visited::Condition(cond) => cond.resolve(&mut defs),
+                Visited::Modifier(modifier) => modifier.resolve(&mut defs),
+            };
+            if let Err(e) = res { errs.push(format!("{}: {e}", at())); }
+        });
+        (self.conditions, self.modifiers) = (defs.conditions, defs.modifiers);
+        if errs.is_empty() { Ok(()) } else { Err(anyhow!("具名定义展开失败:\n{}", errs.join("\n"))) }
+    }
+
+    /// 把条件里的属性名换成属性序号，省得每次判定都按名字去找；脚本用运行时的引擎编译，
+    /// 语法错误一次性报出来。在 resolve 之后调用
+    pub fn compile(&mut self) -> Result<()> {
+        let attrs: Vec<String> = self.player.iter().map(|attr| attr.name.clone()).collect();
+        let engine = ScriptSystem::engine(&self.script);
+        let mut errs = vec![];
+        let mut compile = |script: &mut Script, at: &dyn Fn() -> String| {
+            if let Err(e) = script.compile(&engine) { errs.push(format!("{}: {e}", at())); }
+        };
+        self.visit(&mut |visited, at| {
+            let scripts = match visited {
+                Visited::Condition(cond) => { cond.compile(&attrs); cond.scripts() },
+                Visited::Modifier(modifier) => { modifier.compile(&attrs); modifier.scripts() },
+            };
+            for script in scripts { compile(script, at); }
+        });
+        for evt in &mut self.events {
+            for seg in &mut evt.segments {
+                let Some(script) = &mut seg.text_script else { continue; };
+                compile(script, &|| format!("事件 {} 段落 {} 的文本", evt.name, seg.name));
+            }
+        }
+        if errs.is_empty() { Ok(()) } else { Err(anyhow!("脚本编译失败:\n{}", errs.join("\n"))) }
+    }
+
+    /// 给玩家看的文本：占位符（template.rs）与标记（markup.rs）
+    fn validate_text(&self, text: &str) -> Vec<String> {
+        let mut errs = template::validate(text, self);
+        errs.extend(markup::validate(text, &self.assets));
+        errs
+    }
+
+    /// 读取数据时的检查。问题一次性全部报出来，免得跑到那个事件才发现写错了。
+    pub fn validate(&self) -> Result<()> {
+        let mut errs = vec![];
+        for evt in &self.events {
+            errs.extend(evt.condition.validate(self).into_iter()
+                .map(|e| format!("事件 {}: {e}", evt.name)));
+            for seg in &evt.segments {
+                errs.extend(self.validate_text(&seg.text).into_iter()
+                    .map(|e| format!("事件 {} 段落 {} 的文本: {e}", evt.name, seg.name)));
+                for (i, opt) in seg.options.iter().enumerate() {
+                    let at = format!("事件 {} 段落 {} 选项 {}", evt.name, seg.name, i);
+                    for text in std::iter::once(&opt.text).chain(&opt.disabled_reason) {
+                        errs.extend(self.validate_text(text).into_iter().map(|e| format!("{at} 的文本: {e}")));
+                    }
+                    if let Some(cond) = &opt.condition {
+                        errs.extend(cond.validate(self).into_iter().map(|e| format!("{at}: {e}")));
+                    }
+                    errs.extend(opt.modifier.validate(self).into_iter().map(|e| format!("{at}: {e}")));
+                    if opt.time.duration() < chrono::Duration::zero() { errs.push(format!("{at}: 时间不能倒流")); }
+                    if let Some(seg) = opt.fallback.as_ref().filter(|seg| !evt.segments.iter().any(|s| &s.name == *seg)) {
+                        errs.push(format!("{at}: 没有段落 {seg}"));
+                    }
+                }
+            }
+        }
+        let mut names: Vec<&String> = self.conditions.keys().collect();
+        names.sort();
+        for name in names {
+            errs.extend(self.conditions[name].validate(self).into_iter().map(|e| format!("具名条件 {name}: {e}")));
+        }
+        let mut names: Vec<&String> = self.modifiers.keys().collect();
+        names.sort();
+        for name in names {
+            errs.extend(self.modifiers[name].validate(self).into_iter().map(|e| format!("具名修改 {name}: {e}")));
+        }
+        for item in &self.items {
+            let Some(usage) = &item.usage else { continue; };
+            let at = format!("物品 {} 的使用", item.name);
+            if let Some(cond) = &usage.condition {
+                errs.extend(cond.validate(self).into_iter().map(|e| format!("{at}: {e}")));
+            }
+            errs.extend(usage.modifier.validate(self).into_iter().map(|e| format!("{at}: {e}")));
+            if let Some(evt) = &usage.jump_to_event {
+                if !self.events.iter().any(|e| &e.name == evt) { errs.push(format!("{at}: 未知事件 {evt}")); }
+            }
+        }
+        for map in &self.maps {
+            for conn in &map.connections {
+                let Some(cond) = &conn.condition else { continue; };
+                errs.extend(cond.validate(self).into_iter()
+                    .map(|e| format!("地图 {} -> {}: {e}", map.name, conn.to)));
+            }
+        }
+        for shop in &self.shops {
+            let at = format!("商店 {}", shop.name);
+            if let Some(loc) = &shop.location {
+                if !self.maps.iter().any(|map| &map.name == loc) { errs.push(format!("{at}: 未知地图 {loc}")); }
+            }
+            for goods in &shop.goods {
+                if !self.has_item(&goods.item) { errs.push(format!("{at}: 未知物品 {}", goods.item)); }
+            }
+            for e in shop.hours.iter().flat_map(|hours| hours.validate(self)) {
+                errs.push(format!("{at} 营业时间: {e}"));
+            }
+        }
+        for recipe in &self.recipes {
+            let at = format!("配方 {}", recipe.name);
+            for item in recipe.ingredients.keys().chain(recipe.results.iter().map(|result| &result.item)) {
+                if !self.has_item(item) { errs.push(format!("{at}: 未知物品 {item}")); }
+            }
+            for loc in recipe.location.iter().flatten() {
+                if !self.maps.iter().any(|map| &map.name == loc) { errs.push(format!("{at}: 未知地图 {loc}")); }
+            }
+            for e in recipe.time.iter().flat_map(|time| time.validate(self)) {
+                errs.push(format!("{at}: {e}"));
+            }
+        }
+        if errs.is_empty() { Ok(()) } else { Err(anyhow!("数据检查未通过:\n{}", errs.join("\n"))) }
+    }
+}
+
+enum Visited<'a> {
+    Condition(&'a mut Condition),
+    Modifier(&'a mut Modifier),
 }
 
 pub struct Game {
@@ -83,7 +278,9 @@ impl Game {
         frontend: (Sender<ToFrontend>, Receiver<FromFrontend>),
     )-> Result<Self> {
         let mut ret = Self::new(source, frontend)?;
-        ret.player = player_source.into_data()?;
+        let fresh = std::mem::replace(&mut ret.player, player_source.into_data()?);
+        ret.player.conform(&fresh);
+        ret.frontend.display_inventory(&ret.player, &ret.systems);
         Ok(ret)
     }
 
@@ -91,17 +288,15 @@ impl Game {
         source: DataSource<GameData>,
         frontend: (Sender<ToFrontend>, Receiver<FromFrontend>),
     ) -> Result<Self> {
-        let data = source.into_data()?;
-
-        Ok(Game {
-            systems: Systems { 
-                time: TimeSystem::new(), 
-                map: MapSystem::new(&data.maps),
-                trigger: TriggerSystem::new(&data.trigger), 
-                event: EventSystem::new(&data.events) 
-            },
+        let mut data = source.into_data()?;
+        data.resolve()?;
+        data.validate()?;
+        data.compile()?;
 
-            player: Player::new(&data.player),
+        let mut game = Game {
+            systems: Systems::new(&data),
+
+            player: Player::new(&data.player, &data.currency, &data.protagonist),
 
             frontend: Frontend {
                 sender: frontend.0,
@@ -109,7 +304,24 @@ impl Game {
                 receiver: frontend.1,
                 assets: data.assets
             },
-        })
+        };
+        game.frontend.cache.icons = Some(game.frontend.assets.item_icon.clone());
+        game.report_seed();
+        game.frontend.display_inventory(&game.player, &game.systems);
+        Ok(game)
+    }
+
+    /// 指定随机种子，同样的种子加同样的选择可以重放一局
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        if let Some(seed) = seed {
+            self.player.rng = GameRng::new(seed);
+            self.report_seed();
+        }
+        self
+    }
+
+    fn report_seed(&mut self) {
+        self.frontend.cache.debug = Some(DebugToFrontend { seed: Some(self.player.rng.seed()) });
     }
 
     pub fn main_loop(&mut self) -> Result<(),GameErr> {
@@ -119,14 +331,25 @@ impl Game {
             } = self;
 
             TriggerSystem::set_default(&mut player.trigger);
+            systems.time.release(player);
 
             if let Some(evt) = systems.trigger.pick_event(&player, systems) {
                 player.cur_evt_seg = Some((evt.clone(),None));
             } player.trigger.clear();
+            player.last_checked = Some(player.game_time);
+            // 手头没事时，开始安排好的、已经到点的事件
+            if player.cur_evt_seg.is_none() {
+                player.cur_evt_seg = systems.time.next_due_event(player).map(|evt| (evt, None));
+            }
 
+            frontend.display_ledger(player); // 触发器、到点的安排、调试改的属性
             player.cur_evt_seg = systems.event.process_events(
                 player, systems, frontend,
             )?;
+            // 事件结束后，回到被（物品等）打断的事件
+            if player.cur_evt_seg.is_none() {
+                player.cur_evt_seg = player.evt_stack.pop();
+            }
         }
     }
 
@@ -148,9 +371,12 @@ impl Game {
                         }
                         SetAttribute(str, val) => {
                             if let Some(v) = self.player.attributes.get_mut(&str) {
-                                *v = val;
+                                let before = std::mem::replace(v, val);
+                                self.player.ledger.source = Source::action(&self.player.cur_evt_seg, "调试".to_string());
+                                self.player.ledger.attribute(self.player.game_time, &str, before, val, val);
                             }
                         },
+                        SetSeed(seed) => self = self.with_seed(Some(seed)),
                         None => (),
                     }
                 }
@@ -252,3 +478,237 @@ impl Game {
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::sync::mpsc::channel;
+
+    /// 用 testing::DATA 加 extra 开一局，把 inputs 依次喂给主循环，喂完为止。
+    /// 返回这局游戏和发给前端的所有消息
+    fn play(extra: &str, seed: u64, setup: impl FnOnce(&mut Player), inputs: Vec<FromFrontend>) -> (Game, Vec<ToFrontend>) {
+        let (to_game, from_frontend) = channel();
+        let (to_frontend, received) = channel();
+        let source = DataSource::Raw(testing::source(extra));
+        let mut game = Game::new(source, (to_frontend, from_frontend)).unwrap().with_seed(Some(seed));
+        setup(&mut game.player);
+        for input in inputs { to_game.send(input).unwrap(); }
+        drop(to_game);
+        assert!(game.main_loop().is_err()); // 输入用完，收不到下一个
+        (game, received.try_iter().collect())
+    }
+
+    /// 重放要对得上的部分
+    fn outcome(player: &Player) -> String {
+        let mut items: Vec<_> = player.items.iter().map(|(id, (_, num))| (id, num)).collect();
+        items.sort();
+        let mut history: Vec<_> = player.history.iter().collect();
+        history.sort_by_key(|(name, _)| *name);
+        format!("{:?}\n{items:?}\n{}\n{}\n{history:?}\n{:?}\n{}",
+            player.attributes.val, player.money, player.game_time, player.ledger.entries, player.rng.clone().next_u64())
+    }
+
+    const DICE: &str = r#"
+[[trigger]]
+day = { t = "Always" }
+
+[[items]]
+name = "candy"
+use = { condition = "random() < 0.5", modifier = { attr = "energy", val = { AddRandom = [1, 5] } }, consume = 0 }
+
+[[events]]
+name = "day"
+priority = 1
+force = false
+condition = "random() < 0.8"
+segments = [
+    { name = "start", text = "{if: random() < 0.5 ? 晴 | 雨}", text_script = "if rng.float() < 0.5 { \"晴\" } else { \"雨\" }", options = [
+        { text = "干活", condition = "random() < 0.7", modifier = { attr = "health", val = { AddRandom = [-5, 5] } }, time = { minutes = 30 } },
+        { text = "碰运气", condition = { type = "Script", script = "rng.float() < 0.5" }, modifier = { script = "attr.energy += rng.int(0, 3);" }, time = { minutes = 10 } },
+    ] },
+]
+"#;
+
+    fn choices() -> Vec<FromFrontend> {
+        let mut inputs = vec![];
+        for i in 0..30 {
+            if i % 4 == 0 { inputs.push(FromFrontend::UseItem("candy".into())); }
+            inputs.push(FromFrontend::Choice(i % 2));
+        }
+        inputs
+    }
+
+    fn candy(player: &mut Player) {
+        player.items.insert("candy".into(), (toml::Value::Table(Default::default()), 1));
+    }
+
+    #[test]
+    fn same_seed_and_choices_replay_the_same() {
+        let (first, _) = play(DICE, 42, candy, choices());
+        let (again, _) = play(DICE, 42, candy, choices());
+        assert_eq!(outcome(&first.player), outcome(&again.player));
+        let (other, _) = play(DICE, 7, candy, choices());
+        assert_ne!(outcome(&first.player), outcome(&other.player));
+    }
+
+    #[test]
+    fn display_does_not_draw() {
+        let (Game { systems, player, mut frontend }, _) = play(DICE, 42, candy, choices());
+        let before = outcome(&player);
+        for _ in 0..3 {
+            // 背包里的物品能不能用要掷一次骰子
+            frontend.display_inventory(&player, &systems);
+            frontend.display_shops(&player, &systems);
+            frontend.display_recipes(&player, &systems);
+        }
+        assert_eq!(before, outcome(&player));
+    }
+
+    #[test]
+    fn inventory_is_shown_from_the_start() {
+        let names = |game: &Game| game.frontend.cache.inventory.as_ref()
+            .map(|items| items.iter().map(|item| item.name.clone()).collect::<Vec<_>>());
+        let (to_frontend, _received) = channel();
+        let (_to_game, from_frontend) = channel();
+        let game = Game::new(DataSource::Raw(testing::source(DICE)), (to_frontend, from_frontend)).unwrap();
+        assert_eq!(names(&game), Some(vec![]));
+
+        // 读档后显示存档里的背包
+        let mut saved = game.player.clone();
+        candy(&mut saved);
+        let (to_frontend, _received) = channel();
+        let (_to_game, from_frontend) = channel();
+        let loaded = Game::new_with_player(
+            DataSource::Raw(testing::source(DICE)),
+            DataSource::Inbuilt(Box::new(saved)),
+            (to_frontend, from_frontend),
+        ).unwrap();
+        assert_eq!(names(&loaded), Some(vec!["candy".to_string()]));
+    }
+
+    const BELL: &str = r#"
+[[trigger]]
+morning = { t = "Always" }
+[[trigger]]
+bell = { t = "Custom", c = "bell" }
+[[trigger]]
+quiet = { t = "Custom", c = "bell" }
+
+[[events]]
+name = "morning"
+priority = 1
+force = false
+segments = [
+    { name = "start", text = "早上", options = [
+        { text = "打铃", modifier = [{ trigger = { t = "Custom", c = "bell" } }, { attr = "energy", val = { Add = 1 } }], jump_to = "after" },
+    ] },
+    { name = "after", text = "上课", options = [{ text = "听讲", modifier = { attr = "energy", val = { Add = 2 } } }] },
+]
+
+[[events]]
+name = "bell"
+priority = 2
+force = false
+segments = [{ name = "ring", text = "铃响了", options = [{ text = "好", modifier = { attr = "health", val = { Add = 1 } } }] }]
+
+[[events]]
+name = "quiet"
+priority = 3
+force = false
+condition = "health > 100"
+segments = [{ name = "shh", text = "不该出现", options = [{ text = "好" }] }]
+"#;
+
+    #[test]
+    fn trigger_modifier_fires_before_the_option_moves_on() {
+        let (Game { player, .. }, _) = play(BELL, 1, |_| (), vec![FromFrontend::Choice(0); 3]);
+        let order: Vec<_> = player.ledger.entries.iter()
+            .map(|entry| (entry.source.event.clone().unwrap(), entry.source.segment.clone().unwrap()))
+            .collect();
+        // 铃声插在选项和它要去的段落之间；条件不满足的 quiet 不会被触发
+        assert_eq!(order, [
+            ("morning".to_string(), "start".to_string()),
+            ("bell".into(), "ring".into()),
+            ("morning".into(), "after".into()),
+        ]);
+        assert!(!player.history.contains_key("quiet"));
+    }
+
+    #[test]
+    fn failed_item_use_is_reported() {
+        let extra = r#"
+[[trigger]]
+day = { t = "Always" }
+
+[[items]]
+name = "pill"
+displayed_name = "药片"
+use = { condition = { type = "PlayerAttribute", attributes = { health = { less_than = 50 } } }, modifier = { attr = "health", val = { Add = 30 } } }
+
+[[items]]
+name = "powder"
+displayed_name = "药粉"
+use = { consume = 2, modifier = { attr = "health", val = { Add = 30 } } }
+
+[[items]]
+name = "bandage"
+displayed_name = "绷带"
+use = { modifier = { attr = "health", val = { Add = 10 } } }
+
+[[events]]
+name = "day"
+priority = 1
+force = false
+segments = [{ name = "start", text = "白天", options = [{ text = "好" }] }]
+"#;
+        let pill = |player: &mut Player| {
+            player.items.insert("pill".into(), (toml::Value::Table(Default::default()), 1));
+            player.items.insert("powder".into(), (toml::Value::Table(Default::default()), 1));
+        };
+        let inputs = ["pill", "coffee", "powder", "bandage"].map(|item| FromFrontend::UseItem(item.into())).into_iter()
+            .chain([FromFrontend::Choice(0)])
+            .collect();
+        let (game, sent) = play(extra, 1, pill, inputs);
+        let errors: Vec<_> = sent.iter().filter_map(|msg| msg.error.clone()).collect();
+        assert_eq!(errors, ["现在不能使用药片：health 为 80，需要小于 50", "咖啡不能使用", "药粉不够，需要 2 个", "没有绷带"]);
+        assert_eq!(game.player.items["pill"].1, 1);
+        assert_eq!(game.player.attributes.get("health"), Some(&80));
+    }
+
+    #[test]
+    fn failed_option_goes_to_fallback() {
+        let extra = r#"
+[[trigger]]
+shop = { t = "Always" }
+
+[[items]]
+name = "hat"
+displayed_name = "帽子"
+equip = { slot = "头" }
+
+[[events]]
+name = "shop"
+priority = 1
+force = false
+segments = [
+    { name = "start", text = "买帽子", options = [
+        { text = "戴上", modifier = [{ money = -5 }, { equip = "hat" }], fallback = "oops" },
+        { text = "再试一次", modifier = { equip = "hat" } },
+    ] },
+    { name = "oops", text = "戴不上", options = [{ text = "算了" }] },
+]
+"#;
+        let (game, sent) = play(extra, 1, |_| (), vec![FromFrontend::Choice(0), FromFrontend::Choice(0), FromFrontend::Choice(1)]);
+        let texts: Vec<_> = sent.iter().filter_map(|msg| msg.main_area.clone()).collect();
+        // 出错跳到 fallback；没写 fallback 的留在本段落重选
+        assert_eq!(texts, ["买帽子", "戴不上", "买帽子", "买帽子"]);
+        let errors: Vec<_> = sent.iter().filter_map(|msg| msg.error.clone()).collect();
+        assert_eq!(errors, ["没有帽子，装备不上"; 2]);
+        assert_eq!(game.player.money, 0);
+        // 出错的选项不算选过
+        let record = &game.player.history["shop"];
+        assert!(!record.choices.contains_key("start"));
+        assert_eq!(record.choices["oops"], [1]);
+    }
+}
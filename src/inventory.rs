@@ -0,0 +1,103 @@
+// 背包窗口。数据全部来自后端发来的 ToFrontend::inventory，这里只管排序、筛选与绘制。
+
+use egui::Context;
+
+use crate::{frontend::FromFrontend, MainApp};
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum InventorySort {
+    #[default]
+    Name,
+    Count,
+    Category,
+}
+
+impl InventorySort {
+    fn text(&self) -> &'static str {
+        match self {
+            InventorySort::Name => "名称",
+            InventorySort::Count => "数量",
+            InventorySort::Category => "分类",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct InventoryCache {
+    pub enable: bool,
+    pub sort: InventorySort,
+    pub category: Option<String>, // None 为全部
+}
+
+pub fn inventory_window(app: &mut MainApp, ctx: &Context) {
+    let cache = &mut app.inventory_cache;
+    let sender = &app.backend.sender;
+    let mut items = app.backend.cache.inventory.clone().unwrap_or_default();
+
+    let mut categories: Vec<String> = items.iter()
+        .map(|item| item.category.clone())
+        .filter(|category| !category.is_empty())
+        .collect();
+    categories.sort();
+    categories.dedup();
+
+    if let Some(category) = &cache.category {
+        items.retain(|item| &item.category == category);
+    }
+    match cache.sort {
+        InventorySort::Name => items.sort_by(|a, b| a.name.cmp(&b.name)),
+        InventorySort::Count => items.sort_by(|a, b| b.count.cmp(&a.count).then(a.name.cmp(&b.name))),
+        InventorySort::Category => items.sort_by(|a, b| a.category.cmp(&b.category).then(a.name.cmp(&b.name))),
+    }
+
+    egui::Window::new("背包").open(&mut cache.enable).show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("排序")
+                .selected_text(cache.sort.text())
+                .show_ui(ui, |ui| {
+                    for sort in [InventorySort::Name, InventorySort::Count, InventorySort::Category] {
+                        ui.selectable_value(&mut cache.sort, sort, sort.text());
+                    }
+                });
+            egui::ComboBox::from_label("分类")
+                .selected_text(cache.category.as_deref().unwrap_or("全部"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut cache.category, None, "全部");
+                    for category in categories {
+                        ui.selectable_value(&mut cache.category, Some(category.clone()), category);
+                    }
+                });
+        });
+        ui.separator();
+
+        if items.is_empty() {
+            ui.label("空空如也。");
+            return;
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for item in &items {
+                ui.push_id(&item.id, |ui| ui.horizontal(|ui| {
+                    if let Some(icon) = &item.icon {
+                        ui.add(egui::Image::from_uri(icon.path.clone())
+                            .fit_to_exact_size(icon.size.unwrap_or((32., 32.)).into()));
+                    }
+                    ui.vertical(|ui| {
+                        ui.horizontal(|ui| {
+                            ui.strong(format!("{} ×{}", item.name, item.count));
+                            if let Some(slot) = &item.equipped { ui.weak(format!("已装备于{slot}")); }
+                        });
+                        if !item.description.is_empty() { ui.label(&item.description); }
+                        if !item.tags.is_empty() { ui.small(item.tags.join(" / ")); }
+                        if let Some((text, enabled)) = &item.usage {
+                            if ui.add_enabled(*enabled, egui::Button::new(text)).clicked() {
+                                sender.send(FromFrontend::UseItem(item.id.clone()))
+                                    .unwrap_or_else(|_| panic!("failed to send the item usage to the backend"));
+                            }
+                        }
+                    });
+                }));
+                ui.separator();
+            }
+        });
+    });
+}
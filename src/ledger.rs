@@ -0,0 +1,248 @@
+// 属性与物品的变动账本。测试问“我的精力怎么只剩 3 了”的时候，翻这个就知道是哪个事件、哪个选项扣的。
+// 游戏里只留最近的若干条，存档只带其中最近的一小段。调试界面里可以筛选、导出成 CSV / JSONL 给策划算数值。
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize, Serializer};
+
+pub const MAX_ENTRIES: usize = 5000;
+const SAVED_ENTRIES: usize = 200; // 存档里带多少条，够报 bug 时看最近发生了什么
+
+/// 变动从哪里来
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    pub event: Option<String>,
+    pub segment: Option<String>,
+    pub option: Option<usize>,
+    pub action: Option<String>, // 不是选项引起的，比如“使用 coffee”“调试”
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    Attribute,
+    Item,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub time: NaiveDateTime, // 游戏时间
+    pub source: Source,
+    pub kind: EntryKind,
+    pub name: String,
+    pub before: i64,
+    pub unclamped: i64, // 限制到上下限之前的值。物品没有上下限，和 after 一样
+    pub after: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    #[serde(serialize_with = "Ledger::save_tail")]
+    pub entries: Vec<LedgerEntry>,
+    #[serde(skip)]
+    pub source: Source, // 当前的来源，执行选项、处理玩家操作之前设好
+    #[serde(skip)]
+    sent: Option<usize>, // 已经发给前端的条数；None 为要从头发（刚开局、读档，或者发过的被撤销了）
+    #[serde(skip)]
+    dropped: usize,     // 超出上限被丢掉的条数，算检查点用
+}
+
+impl Source {
+    pub fn action(cur_evt_seg: &Option<(String, Option<String>)>, action: String) -> Self {
+        Self {
+            event: cur_evt_seg.as_ref().map(|(evt, _)| evt.clone()),
+            segment: cur_evt_seg.as_ref().and_then(|(_, seg)| seg.clone()),
+            option: None,
+            action: Some(action),
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(event) = &self.event { parts.push(format!("事件 {event}")); }
+        if let Some(segment) = &self.segment { parts.push(format!("段落 {segment}")); }
+        if let Some(option) = self.option { parts.push(format!("选项 {option}")); }
+        if let Some(action) = &self.action { parts.push(action.clone()); }
+        write!(f, "{}", parts.join(" / "))
+    }
+}
+
+impl Ledger {
+    pub fn attribute(&mut self, time: NaiveDateTime, name: &str, before: i32, unclamped: i32, after: i32) {
+        self.push(EntryKind::Attribute, time, name, before as i64, unclamped as i64, after as i64);
+    }
+
+    pub fn item(&mut self, time: NaiveDateTime, name: &str, before: usize, after: usize) {
+        self.push(EntryKind::Item, time, name, before as i64, after as i64, after as i64);
+    }
+
+    fn push(&mut self, kind: EntryKind, time: NaiveDateTime, name: &str, before: i64, unclamped: i64, after: i64) {
+        if before == unclamped && unclamped == after { return; }
+        self.entries.push(LedgerEntry { time, source: self.source.clone(), kind, name: name.to_string(), before, unclamped, after });
+        if self.entries.len() > MAX_ENTRIES {
+            let dropped = self.entries.len() - MAX_ENTRIES;
+            self.entries.drain(..dropped);
+            self.sent = self.sent.map(|sent| sent.saturating_sub(dropped));
+            self.dropped += dropped;
+        }
+    }
+
+    /// 记下现在记到哪里，配合 rollback 撤销之后记的条目
+    pub fn checkpoint(&self) -> usize {
+        self.dropped + self.entries.len()
+    }
+
+    pub fn rollback(&mut self, checkpoint: usize) {
+        let keep = checkpoint.saturating_sub(self.dropped);
+        self.entries.truncate(keep);
+        if self.sent.is_some_and(|sent| sent > keep) { self.sent = None; }
+    }
+
+    /// 还没发给前端的条目，没有新的就是 None。第一项为真时是从头发的，前端应当清掉旧的
+    pub fn unsent(&mut self) -> Option<(bool, Vec<LedgerEntry>)> {
+        let sent = self.sent.replace(self.entries.len());
+        match sent {
+            None => Some((true, self.entries.clone())),
+            Some(sent) if sent < self.entries.len() => Some((false, self.entries[sent..].to_vec())),
+            Some(_) => None,
+        }
+    }
+
+    fn save_tail<S: Serializer>(entries: &[LedgerEntry], serializer: S) -> Result<S::Ok, S::Error> {
+        entries[entries.len().saturating_sub(SAVED_ENTRIES)..].serialize(serializer)
+    }
+}
+
+/// JSONL 的一行，来源摊平
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    time: String,
+    #[serde(flatten)]
+    source: &'a Source,
+    kind: EntryKind,
+    name: &'a str,
+    before: i64,
+    unclamped: i64,
+    after: i64,
+}
+
+impl LedgerEntry {
+    const CSV_HEADER: &'static str = "time,event,segment,option,action,kind,name,before,unclamped,after";
+
+    pub fn csv(entries: &[&LedgerEntry]) -> String {
+        let quote = |s: &str| if s.contains([',', '"', '\n']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() };
+        let mut out = String::from(Self::CSV_HEADER);
+        for e in entries {
+            let fields = [
+                e.time.to_string(),
+                e.source.event.clone().unwrap_or_default(),
+                e.source.segment.clone().unwrap_or_default(),
+                e.source.option.map(|o| o.to_string()).unwrap_or_default(),
+                e.source.action.clone().unwrap_or_default(),
+                format!("{:?}", e.kind),
+                e.name.clone(),
+                e.before.to_string(),
+                e.unclamped.to_string(),
+                e.after.to_string(),
+            ];
+            out.push('\n');
+            out.push_str(&fields.iter().map(|f| quote(f)).collect::<Vec<_>>().join(","));
+        }
+        out.push('\n');
+        out
+    }
+
+    pub fn jsonl(entries: &[&LedgerEntry]) -> String {
+        entries.iter().map(|e| {
+            let row = JsonRow {
+                time: e.time.to_string(), source: &e.source, kind: e.kind, name: &e.name,
+                before: e.before, unclamped: e.unclamped, after: e.after,
+            };
+            serde_json::to_string(&row).expect("账本条目总能转成 JSON") + "\n"
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minute: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(8, minute % 60, 0).unwrap()
+    }
+
+    fn sizes(unsent: Option<(bool, Vec<LedgerEntry>)>) -> Option<(bool, usize)> {
+        unsent.map(|(from_start, entries)| (from_start, entries.len()))
+    }
+
+    #[test]
+    fn sends_only_changes() {
+        let mut ledger = Ledger::default();
+        assert_eq!(sizes(ledger.unsent()), Some((true, 0))); // 开局清掉前端的旧账本
+        assert_eq!(sizes(ledger.unsent()), None);
+        ledger.attribute(at(0), "energy", 10, 10, 10); // 没变不记
+        assert_eq!(sizes(ledger.unsent()), None);
+        ledger.attribute(at(0), "energy", 10, 15, 15);
+        ledger.item(at(1), "coffee", 0, 2);
+        assert_eq!(sizes(ledger.unsent()), Some((false, 2)));
+        assert_eq!(sizes(ledger.unsent()), None);
+
+        // 撤销还没发的，照常接着发；撤销了已经发过的，从头发
+        let checkpoint = ledger.checkpoint();
+        ledger.item(at(2), "coffee", 2, 1);
+        ledger.rollback(checkpoint);
+        assert_eq!(sizes(ledger.unsent()), None);
+        ledger.rollback(1);
+        assert_eq!(sizes(ledger.unsent()), Some((true, 1)));
+    }
+
+    #[test]
+    fn keeps_recent_entries() {
+        let mut ledger = Ledger::default();
+        ledger.unsent();
+        for i in 0..MAX_ENTRIES + 10 {
+            ledger.attribute(at(i as u32), "energy", i as i32, i as i32 + 1, i as i32 + 1);
+        }
+        assert_eq!(ledger.entries.len(), MAX_ENTRIES);
+        assert_eq!(ledger.entries[0].before, 10);
+        assert_eq!(sizes(ledger.unsent()), Some((false, MAX_ENTRIES)));
+        // 检查点按总条数算，撤销时不会错删；为腾地方丢掉的最早一条回不来
+        let checkpoint = ledger.checkpoint();
+        ledger.item(at(0), "coffee", 0, 1);
+        ledger.rollback(checkpoint);
+        assert_eq!(ledger.entries.len(), MAX_ENTRIES - 1);
+        assert_eq!(ledger.entries.last().unwrap().name, "energy");
+        assert_eq!(sizes(ledger.unsent()), None);
+
+        // 存档只带最近的一小段
+        let saved = serde_json::to_string(&ledger).unwrap();
+        let mut loaded: Ledger = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded.entries.len(), SAVED_ENTRIES);
+        assert_eq!(loaded.entries.last().unwrap().after, ledger.entries.last().unwrap().after);
+        assert_eq!(sizes(loaded.unsent()), Some((true, SAVED_ENTRIES)));
+    }
+
+    #[test]
+    fn exports() {
+        let source = Source { event: Some("说\"你好\"".into()), segment: Some("a,b".into()), option: Some(1), action: None };
+        let mut ledger = Ledger { source, ..Default::default() };
+        ledger.attribute(at(0), "energy", 10, -5, 0);
+        ledger.source = Source::action(&None, "使用 coffee\n两次".into());
+        ledger.item(at(5), "coffee", 2, 0);
+        let entries: Vec<_> = ledger.entries.iter().collect();
+
+        assert_eq!(LedgerEntry::csv(&entries), "\
+time,event,segment,option,action,kind,name,before,unclamped,after
+2024-03-01 08:00:00,\"说\"\"你好\"\"\",\"a,b\",1,,Attribute,energy,10,-5,0
+2024-03-01 08:05:00,,,,\"使用 coffee\n两次\",Item,coffee,2,0,0
+");
+        let jsonl = LedgerEntry::jsonl(&entries);
+        assert_eq!(jsonl.lines().next().unwrap(), concat!(
+            r#"{"time":"2024-03-01 08:00:00","event":"说\"你好\"","segment":"a,b","option":1,"action":null,"#,
+            r#""kind":"Attribute","name":"energy","before":10,"unclamped":-5,"after":0}"#,
+        ));
+        let rows: Vec<serde_json::Value> = jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(rows[1]["action"], "使用 coffee\n两次");
+        assert_eq!(rows[1]["event"], serde_json::Value::Null);
+    }
+}
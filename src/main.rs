@@ -2,6 +2,10 @@
 use frontend::{FromFrontend, ToFrontend,assets::ImageData};
 // hide console window on Windows in release
 use game::{Game, DataSource};
+use craft::CraftCache;
+use inventory::InventoryCache;
+use shop::ShopCache;
+use toast::ToastCache;
 use eframe::egui;
 use egui::FontDefinitions;
 use std::{
@@ -9,12 +13,23 @@ use std::{
     thread, vec,
 };
 
+#[cfg(test)]
+mod bench;
 mod events;
 mod frontend;
 mod game;
+mod ledger;
 mod player;
+mod rng;
 mod systems;
 mod debug;
+mod inventory;
+mod craft;
+mod shop;
+mod rich_text;
+mod toast;
+#[cfg(test)]
+mod testing;
 
 fn main() -> eframe::Result {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
@@ -50,6 +65,10 @@ struct MainApp {
     backend: Backend,
     persistence: Persistence,
     debug_cache: DebugCache,
+    inventory_cache: InventoryCache,
+    shop_cache: ShopCache,
+    craft_cache: CraftCache,
+    toast_cache: ToastCache,
 }
 
 struct Persistence {
@@ -82,7 +101,26 @@ struct DebugCache {
     path_str: String,
     attr_str: String,
     value: i32,
+    seed_str: String,
     enable: bool,
+    ledger: LedgerFilter,
+}
+
+/// 账本窗口的筛选条件，空的不筛
+#[derive(Default)]
+struct LedgerFilter {
+    show: bool,
+    kind: Option<ledger::EntryKind>,
+    name: String,
+    source: String,
+    status: String, // 导出的结果
+}
+
+/// 启动时指定随机种子：命令行 `--seed 12345`，或者环境变量 USTCDAYS_SEED
+fn launch_seed() -> Option<u64> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--seed").skip(1);
+    args.next().or_else(|| std::env::var("USTCDAYS_SEED").ok())
+        .and_then(|seed| seed.trim().parse().ok())
 }
 
 impl Default for MainApp {
@@ -95,7 +133,7 @@ impl Default for MainApp {
                     r#"./src/data/example.toml"#.into(),
                 ), (sf, ru),
             )
-            .unwrap().run();
+            .unwrap().with_seed(launch_seed()).run();
         });
         Self {
             backend: Backend {
@@ -104,7 +142,11 @@ impl Default for MainApp {
                 cache: ToFrontend::new(),
             },
             persistence: Persistence::default(),
-            debug_cache: DebugCache::default()
+            debug_cache: DebugCache::default(),
+            inventory_cache: InventoryCache::default(),
+            shop_cache: ShopCache::default(),
+            craft_cache: CraftCache::default(),
+            toast_cache: ToastCache::default(),
         }
     }
 }
@@ -112,7 +154,14 @@ impl Default for MainApp {
 impl eframe::App for MainApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.try_frontend_update();
-        if self.debug_cache.enable { debug::debug_window(self, ctx); }
+        if self.debug_cache.enable {
+            debug::debug_window(self, ctx);
+            debug::ledger_window(self, ctx);
+        }
+        inventory::inventory_window(self, ctx);
+        shop::shop_window(self, ctx);
+        craft::craft_window(self, ctx);
+        toast::toasts(self, ctx);
         egui::SidePanel::left("PlayerStateBar")
             .resizable(false)
             .show(ctx, |ui| {
@@ -138,15 +187,30 @@ impl eframe::App for MainApp {
                         ui.add(egui::ProgressBar::new((*cur as f32) / (*max as f32)))
                             .labelled_by(ui.label(name).id);
                 }
+                ui.add_space(16.);
+                if let Some((currency, money)) = &self.backend.cache.money {
+                    ui.label(format!("{money} {currency}"));
+                }
+                ui.toggle_value(&mut self.inventory_cache.enable, "背包");
+                ui.toggle_value(&mut self.craft_cache.enable, "合成");
+                if self.backend.cache.shops.as_ref().is_some_and(|shops| !shops.is_empty()) {
+                    ui.toggle_value(&mut self.shop_cache.enable, "商店");
+                }
             });
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("今日日程");
-            ui.label(self.backend.cache.main_area.clone().unwrap_or_default());
+            let icons = self.backend.cache.icons.clone().unwrap_or_default();
+            rich_text::show(ui, self.backend.cache.main_area.as_deref().unwrap_or_default(), &icons);
+            if let Some(error) = &self.backend.cache.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
 
             let options = self.backend.cache.option_area.clone();
-            for (id, (opt_name,enabled)) in options.unwrap_or_default().into_iter().enumerate() {
-                let button = egui::Button::new(opt_name);
-                if ui.add_enabled(enabled, button).clicked() {
+            for (id, (opt_name,enabled,reason)) in options.unwrap_or_default().into_iter().enumerate() {
+                let button = ui.add_enabled(enabled, egui::Button::new(rich_text::layout_job(&opt_name, ui)));
+                if let Some(reason) = reason {
+                    button.on_disabled_hover_text(rich_text::layout_job(&reason, ui));
+                } else if button.clicked() {
                     self.backend.send(FromFrontend::Choice(id))
                         .unwrap_or_else(|_| panic!("failed to send the selection to the backend"));
                     break;
@@ -2,7 +2,10 @@ use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
-use crate::events::{modifier::{Identity, ValModifier}, triggers::Trigger};
+use crate::events::{changes::Change, history::EventRecord, modifier::{Identity, ValModifier}, schedule::{Pending, Scheduled}, triggers::Trigger};
+use crate::ledger::Ledger;
+use crate::rng::GameRng;
+use crate::systems::{shop_system::Currency, Systems};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Attribute {
@@ -25,7 +28,16 @@ pub struct Attribute {
     pub invisible: bool,
 }
 
-#[derive(Serialize,Deserialize,Default)]
+/// 主角。名字、性别目前只在文本模板里用，见 template.rs
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Protagonist {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub gender: String,
+}
+
+#[derive(Serialize,Deserialize,Default,Clone)]
 pub struct PlayerAttribute {
     pub val: Vec<(String,i32)>,
     // 这样的实现旨在节约内存——attributes作为直接给玩家看的属性，确实不需要太多条，如果不爽请修改成HashMap实现。
@@ -33,10 +45,10 @@ pub struct PlayerAttribute {
 
 impl PlayerAttribute {
     pub fn get_mut(&mut self,k: &str) -> Option<&mut i32> {
-        Some(&mut self.val.iter_mut().filter(|(str,_)| str != k).next()?.1)
+        Some(&mut self.val.iter_mut().find(|(str,_)| str == k)?.1)
     }
     pub fn get(&self,k: &str) -> Option<&i32> {
-        Some(&self.val.iter().filter(|(str,_)| str != k).next()?.1)
+        Some(&self.val.iter().find(|(str,_)| str == k)?.1)
     }
 
     pub fn id_mut(&mut self,k: &crate::events::modifier::Identity) -> Option<&mut i32> {
@@ -47,10 +59,10 @@ impl PlayerAttribute {
         }
     }
 
-    pub fn id_mut_with_name<'a>(&'a mut self,k: &'a crate::events::modifier::Identity) -> Option<(&mut i32,&'a String)> {
+    pub fn id_with_name<'a>(&'a self,k: &'a crate::events::modifier::Identity) -> Option<(i32,&'a String)> {
         match k {
-            Identity::Str(k) => self.get_mut(k).map(|i|(i,k)),
-            Identity::Index(i) => self.val.get_mut(*i).map(|v| (&mut v.1,&v.0)),
+            Identity::Str(k) => self.get(k).map(|i|(*i,k)),
+            Identity::Index(i) => self.val.get(*i).map(|v| (v.1,&v.0)),
             Identity::None => None,
         }
     }
@@ -60,22 +72,49 @@ impl PlayerAttribute {
     }
 }
 
+// 物品 id -> (实例覆盖属性, 数量)。物品本身的名称、描述、默认属性等见 ItemDef。
 pub type PlayerItem = HashMap<String,(toml::Value,usize)>;
 
-#[derive(Serialize,Deserialize,Default)]
+#[derive(Serialize,Deserialize,Default,Clone)]
 pub struct Player {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub gender: String,
     pub attributes: PlayerAttribute,
     pub attribute_defs: HashMap<String, Attribute>,
 
     pub items: PlayerItem,
+    #[serde(default)]
+    pub equipment: HashMap<String, String>, // 装备栏 -> 物品 id。装备着的物品仍然留在背包里
+    #[serde(default)]
+    pub money: i32,
+    #[serde(default)]
+    pub shop_sold: HashMap<String, HashMap<String, usize>>, // 商店 -> 物品 -> 已售出数量，用来算剩余库存
+    #[serde(default)]
+    pub known_recipes: HashSet<String>, // 学会的配方，不含一开始就会的
     pub game_time: NaiveDateTime,
+    #[serde(default)]
+    pub last_checked: Option<NaiveDateTime>, // 上一轮挑事件时的游戏时间，时间条件里的 times 用
     pub game_map: String,
     pub cur_evt_seg: Option<(String, Option<String>)>,
+    #[serde(default)]
+    pub evt_stack: Vec<(String, Option<String>)>, // 被打断、等待继续的事件
+    #[serde(default)]
+    pub history: HashMap<String, EventRecord>, // 事件 -> 经历
+    #[serde(default)]
+    pub rng: GameRng, // 游戏里唯一的随机源
+    #[serde(default)]
+    pub scheduled: Vec<Scheduled>, // 安排在将来的事，按时间先后排好
+    #[serde(skip)]
+    pub changes: Vec<Change>, // 还没告诉前端的变化
+    #[serde(default)]
+    pub ledger: Ledger, // 属性、物品的变动记录
     pub trigger: HashSet<Trigger>,
 }
 
 impl Player {
-    pub fn new(attribute: &Vec<Attribute>) -> Self {
+    pub fn new(attribute: &Vec<Attribute>, currency: &Currency, protagonist: &Protagonist) -> Self {
         let mut attributes = PlayerAttribute { val: vec![] };
         let mut defs_map = HashMap::new();
         for attr in attribute.iter() {
@@ -84,11 +123,18 @@ impl Player {
         }
 
         Self {
+            name: protagonist.name.clone(),
+            gender: protagonist.gender.clone(),
             attributes,
             attribute_defs: defs_map,
             items: HashMap::new(),
+            equipment: HashMap::new(),
+            money: currency.initial,
+            shop_sold: HashMap::new(),
+            known_recipes: HashSet::new(),
             game_time: chrono::NaiveDateTime::parse_from_str("2024-01-01 00:00", "%Y-%m-%d %H:%M")
                 .unwrap(),
+            last_checked: None,
             game_map: "Town".to_string(),
 
             trigger: {
@@ -97,20 +143,69 @@ impl Player {
                 trigger
             },
             cur_evt_seg: None,
+            evt_stack: vec![],
+            history: HashMap::new(),
+            rng: GameRng::from_entropy(),
+            scheduled: vec![],
+            changes: vec![],
+            ledger: Ledger::default(),
         }
     }
 
-    pub fn modify_attribute(&mut self, attr: &Identity, value: &ValModifier) {
-        if let Some((current,k)) = self.attributes.id_mut_with_name(attr) {
-            value.apply(current);
+    /// 安排在 at 时发生。同一时刻的按安排的先后
+    pub fn schedule(&mut self, at: NaiveDateTime, what: Pending) {
+        let i = self.scheduled.partition_point(|s| s.at <= at);
+        self.scheduled.insert(i, Scheduled { at, what });
+    }
+
+    pub fn modify_attribute(&mut self, systems: &Systems, attr: &Identity, value: &ValModifier) {
+        if let Some((current,k)) = self.attributes.id_with_name(attr) {
+            let k = k.clone();
+            let max = self.attribute_defs.get(&k).map(|def| def.max);
+            let before = current;
+            let mut current = value.apply(current, max, systems, self);
+            let unclamped = current;
             // 检查属性上限和下限
-            if *current > self.attribute_defs.get(k).unwrap().max {
-                *current = self.attribute_defs.get(k).unwrap().max;
+            if current > self.attribute_defs.get(&k).unwrap().max {
+                current = self.attribute_defs.get(&k).unwrap().max;
             }
-            if *current < self.attribute_defs.get(k).unwrap().min {
-                *current = self.attribute_defs.get(k).unwrap().min;
+            if current < self.attribute_defs.get(&k).unwrap().min {
+                current = self.attribute_defs.get(&k).unwrap().min;
             }
+            *self.attributes.get_mut(&k).unwrap() = current;
+            self.ledger.attribute(self.game_time, &k, before, unclamped, current);
+        }
+    }
+
+    /// 实际生效的属性值：基础值加上装备加成
+    pub fn attribute(&self, systems: &Systems, k: &str) -> Option<i32> {
+        self.attribute_at(systems, k, None)
+    }
+
+    /// 给出编译时记下的序号就直接按序号取，没有才按名字找。
+    /// 属性的排列总是和数据里的一致（读档时由 conform 保证），所以序号不用再核对名字
+    pub fn attribute_at(&self, systems: &Systems, k: &str, index: Option<usize>) -> Option<i32> {
+        let value = match index {
+            Some(i) => self.attributes.val.get(i)?.1,
+            None => *self.attributes.get(k)?,
+        };
+        Some(value + systems.item.attribute_bonus(self, k))
+    }
+
+    /// 读档后把属性按 fresh（按当前数据新建的玩家）的排列重排：存档里没有的取默认值，
+    /// 数据里已经删掉的丢弃，属性定义也换成当前的。这样编译出的属性序号对存档同样有效
+    pub fn conform(&mut self, fresh: &Player) {
+        let saved = std::mem::take(&mut self.attributes.val);
+        for (name, _) in &saved {
+            if fresh.attributes.get(name).is_none() { eprintln!("ERROR: 存档里的属性 {name} 已不存在，丢弃"); }
         }
+        self.attributes.val = fresh.attributes.val.iter()
+            .map(|(name, default)| {
+                let value = saved.iter().find(|(n, _)| n == name).map_or(*default, |(_, v)| *v);
+                (name.clone(), value)
+            })
+            .collect();
+        self.attribute_defs = fresh.attribute_defs.clone();
     }
 
     pub fn get_over_under_descriptions(&self) -> Vec<String> {
@@ -0,0 +1,94 @@
+// 把文本里的标记（见 frontend/markup.rs）画出来。文字拼成 LayoutJob；
+// 图标和链接得是单独的控件，有它们时改在 horizontal_wrapped 里一段段地排。
+// 字体只有一种字重，粗体用醒目的文字颜色代替。
+
+use std::collections::HashMap;
+
+use egui::{text::LayoutJob, Align, Color32, Stroke, TextFormat, TextStyle, Ui};
+
+use crate::frontend::{assets::ImageData, markup::{self, Span, Style}};
+
+const RUBY_SCALE: f32 = 0.6;
+
+fn format(text: &str, style: &Style, ui: &Ui) -> TextFormat {
+    let visuals = ui.visuals();
+    let color = match (style.color, style.speaker, &style.link) {
+        (Some((r, g, b)), _, _) => Color32::from_rgb(r, g, b),
+        (None, true, _) => {
+            let (r, g, b) = markup::speaker_color(text.trim());
+            Color32::from_rgb(r, g, b)
+        },
+        (None, false, Some(_)) => visuals.hyperlink_color,
+        (None, false, None) if style.bold => visuals.strong_text_color(),
+        (None, false, None) => visuals.text_color(),
+    };
+    let line = Stroke::new(1., color);
+    TextFormat {
+        font_id: TextStyle::Body.resolve(ui.style()),
+        color,
+        italics: style.italics,
+        underline: if style.underline { line } else { Stroke::NONE },
+        strikethrough: if style.strikethrough { line } else { Stroke::NONE },
+        ..Default::default()
+    }
+}
+
+fn append(job: &mut LayoutJob, span: &Span, ui: &Ui) {
+    match span {
+        Span::Text(text, style) => job.append(text, 0., format(text, style, ui)),
+        Span::Ruby(ruby, style) => {
+            let mut format = format(ruby, style, ui);
+            format.font_id.size *= RUBY_SCALE;
+            format.valign = Align::TOP;
+            job.append(ruby, 1., format);
+        },
+        Span::Icon(_) => (),
+    }
+}
+
+/// 只有文字的版本，给按钮、悬停提示这类放不进别的控件的地方。图标略去，链接只是变色
+pub fn layout_job(text: &str, ui: &Ui) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    for span in markup::parse(text) { append(&mut job, &span, ui); }
+    job
+}
+
+/// 画一段带标记的文本
+pub fn show(ui: &mut Ui, text: &str, icons: &HashMap<String, ImageData>) {
+    let spans = markup::parse(text);
+    let widgets = spans.iter().any(|span| matches!(span, Span::Icon(_) | Span::Text(_, Style { link: Some(_), .. })));
+    if !widgets {
+        let mut job = LayoutJob::default();
+        for span in &spans { append(&mut job, span, ui); }
+        ui.label(job);
+        return;
+    }
+
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.;
+        let flush = |job: &mut LayoutJob, ui: &mut Ui| {
+            if !job.is_empty() { ui.label(std::mem::take(job)); }
+        };
+        let mut job = LayoutJob::default();
+        for span in &spans {
+            match span {
+                Span::Icon(name) => {
+                    flush(&mut job, ui);
+                    // 行内图标和字一样高，不用背包里的尺寸
+                    let height = ui.text_style_height(&TextStyle::Body);
+                    if let Some(icon) = icons.get(name) {
+                        ui.add(egui::Image::from_uri(icon.path.clone()).fit_to_exact_size(egui::vec2(height, height)));
+                    }
+                },
+                Span::Text(text, style @ Style { link: Some(url), .. }) => {
+                    flush(&mut job, ui);
+                    let mut link = LayoutJob::default();
+                    link.append(text, 0., format(text, style, ui));
+                    ui.hyperlink_to(link, url);
+                },
+                span => append(&mut job, span, ui),
+            }
+        }
+        flush(&mut job, ui);
+    });
+}
@@ -0,0 +1,91 @@
+// 游戏里所有的随机数都从这里出。种子与当前状态跟着存档走，
+// 同样的种子加上同样的选择，就能把一局游戏原样重放出来（报 bug、写自动化测试都靠它）。
+//
+// 算法是 SplitMix64：够快、状态只有一个 u64、方便存档。
+// 状态用原子量是为了在只拿到 &Player 的地方（判定条件时）也能取随机数。
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(from = "SavedRng", into = "SavedRng")]
+pub struct GameRng {
+    seed: u64,
+    state: AtomicU64,
+}
+
+/// 存档用。TOML 的整数只有 i64，所以按位转成 i64 存
+#[derive(Serialize, Deserialize)]
+struct SavedRng {
+    seed: i64,
+    state: i64,
+}
+
+impl From<SavedRng> for GameRng {
+    fn from(saved: SavedRng) -> Self {
+        Self { seed: saved.seed as u64, state: AtomicU64::new(saved.state as u64) }
+    }
+}
+
+impl From<GameRng> for SavedRng {
+    fn from(rng: GameRng) -> Self {
+        Self { seed: rng.seed as i64, state: rng.state.into_inner() as i64 }
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: AtomicU64::new(seed) }
+    }
+
+    /// 没有指定种子时随便取一个。整个游戏只有这里碰系统的随机源
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let mut z = self.state.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// 只给玩家看的判定（选项能不能选、物品能不能用、段落文字）放在这里面做。
+    /// 里面照常取随机数，做完把状态拨回去：同样的状态看几次都一样，界面刷新多少次也不影响重放
+    pub fn preview<T>(&self, f: impl FnOnce() -> T) -> T {
+        let state = self.state.load(Ordering::Relaxed);
+        let ret = f();
+        self.state.store(state, Ordering::Relaxed);
+        ret
+    }
+
+    /// [0, 1)
+    pub fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// [lo, hi] 闭区间，lo > hi 时两者对调
+    pub fn range(&self, lo: i64, hi: i64) -> i64 {
+        let (lo, hi) = (lo.min(hi), lo.max(hi));
+        let span = (hi.wrapping_sub(lo) as u64).wrapping_add(1);
+        if span == 0 { return self.next_u64() as i64; } // 整个 i64 范围
+        lo.wrapping_add((self.next_u64() % span) as i64)
+    }
+}
+
+impl Clone for GameRng {
+    fn clone(&self) -> Self {
+        Self { seed: self.seed, state: AtomicU64::new(self.state.load(Ordering::Relaxed)) }
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
@@ -0,0 +1,60 @@
+// 商店窗口。根据后端发来的 ToFrontend::shops 自动生成，不需要为每个商店单独写界面。
+
+use egui::Context;
+
+use crate::{frontend::FromFrontend, MainApp};
+
+#[derive(Default)]
+pub struct ShopCache {
+    pub enable: bool,
+    pub affordable_only: bool,
+}
+
+pub fn shop_window(app: &mut MainApp, ctx: &Context) {
+    let cache = &mut app.shop_cache;
+    let sender = &app.backend.sender;
+    let shops = app.backend.cache.shops.clone().unwrap_or_default();
+    let (currency, money) = app.backend.cache.money.clone().unwrap_or_default();
+    if shops.is_empty() { return; }
+
+    egui::Window::new("商店").open(&mut cache.enable).show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.label(format!("余额：{money} {currency}"));
+            ui.checkbox(&mut cache.affordable_only, "只看买得起的");
+        });
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for shop in &shops {
+                ui.push_id(&shop.id, |ui| {
+                    ui.heading(&shop.name);
+                    egui::Grid::new("goods").striped(true).show(ui, |ui| {
+                        for goods in &shop.goods {
+                            let affordable = goods.price <= money;
+                            let in_stock = goods.stock.is_none_or(|stock| stock > 0);
+                            if cache.affordable_only && !(affordable && in_stock) { continue; }
+
+                            ui.label(&goods.name);
+                            ui.label(format!("{} {currency}", goods.price));
+                            ui.label(goods.stock.map_or("不限".into(), |stock| format!("剩 {stock}")));
+                            if ui.add_enabled(affordable && in_stock, egui::Button::new("买")).clicked() {
+                                sender.send(FromFrontend::Buy {
+                                    shop: shop.id.clone(), item: goods.item.clone(), count: 1
+                                }).unwrap_or_else(|_| panic!("failed to send the purchase to the backend"));
+                            }
+                            match goods.sell_price {
+                                Some(price) => if ui.add_enabled(goods.owned > 0,
+                                    egui::Button::new(format!("卖 ({price} {currency})"))).clicked() {
+                                    sender.send(FromFrontend::Sell {
+                                        shop: shop.id.clone(), item: goods.item.clone(), count: 1
+                                    }).unwrap_or_else(|_| panic!("failed to send the sale to the backend"));
+                                },
+                                None => { ui.label(""); },
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+                ui.separator();
+            }
+        });
+    });
+}
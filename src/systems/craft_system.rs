@@ -0,0 +1,202 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{events::conditions::TimeCondition, player::{Player, PlayerItem}};
+
+use super::Systems;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RecipeResult {
+    pub item: String,
+    #[serde(default = "RecipeResult::default_count")]
+    pub count: usize,
+    pub val: Option<toml::Value>, // 产物的实例属性，覆盖定义中的默认属性
+}
+
+impl RecipeResult {
+    fn default_count() -> usize { 1 }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Recipe {
+    pub name: String,
+    pub displayed_name: Option<String>,
+    pub ingredients: HashMap<String, usize>, // 物品 -> 消耗数量
+    pub results: Vec<RecipeResult>,
+    pub location: Option<Vec<String>>,       // 只能在这些地图上做
+    pub time: Option<TimeCondition>,         // 只能在这些时间做
+    #[serde(default)]
+    pub known: bool,                         // 一开始就会，不用学
+}
+
+pub struct CraftSystem {
+    pub recipes: HashMap<String, Recipe>,
+}
+
+impl CraftSystem {
+    pub fn new(recipes: &Vec<Recipe>) -> Self {
+        let mut recipe_hash = HashMap::new();
+        for recipe in recipes {
+            recipe_hash.insert(recipe.name.clone(), recipe.clone());
+        }
+
+        Self {
+            recipes: recipe_hash,
+        }
+    }
+
+    pub fn displayed_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.recipes.get(id)
+            .and_then(|recipe| recipe.displayed_name.as_deref())
+            .unwrap_or(id)
+    }
+
+    pub fn is_known(&self, player: &Player, id: &str) -> bool {
+        self.recipes.get(id).is_some_and(|recipe| recipe.known)
+            || player.known_recipes.contains(id)
+    }
+
+    /// 玩家会的所有配方
+    pub fn known<'a>(&'a self, player: &'a Player) -> impl Iterator<Item = &'a Recipe> {
+        self.recipes.values().filter(|recipe| self.is_known(player, &recipe.name))
+    }
+
+    /// 做一次。在背包的副本上先算一遍，全部检查通过才写回，所以要么全成要么什么都不变。
+    /// 失败时返回原因，前端拿它来解释为什么不能做。
+    pub fn craft(systems: &Systems, player: &mut Player, id: &str) -> Result<()> {
+        let items = Self::try_craft(systems, player, id)?;
+        player.items = items;
+        player.equipment.retain(|_, id| player.items.contains_key(id));
+        Ok(())
+    }
+
+    pub fn can_craft(systems: &Systems, player: &Player, id: &str) -> Result<()> {
+        Self::try_craft(systems, player, id).map(|_| ())
+    }
+
+    fn try_craft(systems: &Systems, player: &Player, id: &str) -> Result<PlayerItem> {
+        let recipe = systems.craft.recipes.get(id).ok_or(anyhow!("未知配方 {id}"))?;
+        if !systems.craft.is_known(player, id) {
+            return Err(anyhow!("还不会做"));
+        }
+        if recipe.location.as_ref().is_some_and(|locs| !locs.contains(&player.game_map)) {
+            return Err(anyhow!("这里做不了"));
+        }
+        if recipe.time.as_ref().is_some_and(|time| !systems.time.check_condition(player, time)) {
+            return Err(anyhow!("现在做不了"));
+        }
+
+        let mut items = player.items.clone();
+        for (item, need) in &recipe.ingredients {
+            let Some((_, num)) = items.get_mut(item).filter(|(_, num)| *num >= *need)
+                else { return Err(anyhow!("{}不够", systems.item.displayed_name(item))); };
+            *num -= need;
+            if *num == 0 { items.remove(item); }
+        }
+        for result in &recipe.results {
+            if systems.item.free_space(&items, Some(&result.item)).is_some_and(|space| space < result.count) {
+                return Err(anyhow!("放不下了"));
+            }
+            let entry = items.entry(result.item.clone())
+                .or_insert_with(|| (toml::Value::Table(toml::Table::new()), 0));
+            entry.1 += result.count;
+            if let Some(val) = &result.val { entry.0 = val.clone(); }
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const KITCHEN: &str = r#"
+[[items]]
+name = "milk"
+displayed_name = "牛奶"
+
+[[items]]
+name = "latte"
+
+[[items]]
+name = "tea"
+max_stack = 3
+
+[[recipes]]
+name = "latte"
+ingredients = { coffee = 2, milk = 1 }
+results = [{ item = "latte", val = { hot = true } }]
+known = true
+
+[[recipes]]
+name = "brew"
+ingredients = { coffee = 1 }
+results = [{ item = "tea", count = 4 }]
+known = true
+
+[[recipes]]
+name = "secret"
+ingredients = { coffee = 1 }
+results = [{ item = "latte" }]
+
+[[recipes]]
+name = "bakery"
+ingredients = { coffee = 1 }
+results = [{ item = "latte" }]
+location = ["Bakery"]
+known = true
+
+[[recipes]]
+name = "night"
+ingredients = { coffee = 1 }
+results = [{ item = "latte" }]
+time = { start = "22:00", end = "23:00" }
+known = true
+"#;
+
+    fn give(player: &mut Player, item: &str, count: usize) {
+        player.items.insert(item.into(), (toml::Value::Table(Default::default()), count));
+    }
+
+    fn counts(player: &Player) -> Vec<(String, usize)> {
+        let mut items: Vec<_> = player.items.iter().map(|(id, (_, num))| (id.clone(), *num)).collect();
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn craft() {
+        let (_, systems, mut player) = testing::setup(KITCHEN);
+        give(&mut player, "coffee", 2);
+        give(&mut player, "milk", 1);
+        CraftSystem::craft(&systems, &mut player, "latte").unwrap();
+        assert_eq!(counts(&player), [("latte".to_string(), 1)]); // 用光的原料不留空条目
+        assert_eq!(player.items["latte"].0, toml::Value::Table(toml::toml! { hot = true }));
+    }
+
+    #[test]
+    fn failures_leave_the_inventory_untouched() {
+        let (_, systems, mut player) = testing::setup(KITCHEN);
+        give(&mut player, "coffee", 3);
+        give(&mut player, "tea", 1);
+        let before = counts(&player);
+        for (recipe, expected) in [
+            ("latte", "牛奶不够"), // 咖啡够，牛奶不够：咖啡也不能先扣掉
+            ("brew", "放不下了"),  // 原料扣了之后才发现放不下
+            ("secret", "还不会做"),
+            ("bakery", "这里做不了"),
+            ("night", "现在做不了"),
+            ("nope", "未知配方 nope"),
+        ] {
+            assert_eq!(CraftSystem::can_craft(&systems, &player, recipe).unwrap_err().to_string(), expected);
+            assert_eq!(CraftSystem::craft(&systems, &mut player, recipe).unwrap_err().to_string(), expected);
+            assert_eq!(counts(&player), before, "{recipe}");
+        }
+        // 学会以后就能做了
+        player.known_recipes.insert("secret".into());
+        CraftSystem::craft(&systems, &mut player, "secret").unwrap();
+        assert_eq!(counts(&player), [("coffee".to_string(), 2), ("latte".into(), 1), ("tea".into(), 1)]);
+    }
+}
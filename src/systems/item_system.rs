@@ -0,0 +1,178 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{
+    events::{changes::Change, conditions::Condition, modifier::{Modifier, ValModifier}},
+    player::{Player, PlayerItem},
+};
+
+use super::Systems;
+
+/// 物品定义。玩家身上的物品只记录 id、数量与实例自己的覆盖属性，其余都从这里取。
+#[derive(Debug, Deserialize, Clone)]
+pub struct ItemDef {
+    pub name: String,
+    pub displayed_name: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    pub icon: Option<String>,       // assets 中图标的键
+    pub max_stack: Option<usize>,   // 单种物品最多持有多少个，None 为不限
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub properties: toml::Table,    // 默认属性，实例可以逐项覆盖
+    #[serde(rename = "use")]
+    pub usage: Option<ItemUse>,
+    pub equip: Option<ItemEquip>,
+}
+
+/// 物品的“使用”：修改属性，或者开始一个事件（也可以都做）
+#[derive(Debug, Deserialize, Clone)]
+pub struct ItemUse {
+    pub text: Option<String>,          // 按钮文本，默认为“使用”
+    pub condition: Option<Condition>,
+    #[serde(default)]
+    pub modifier: Modifier,
+    pub jump_to_event: Option<String>, // 插队执行，结束后回到原来的事件
+    #[serde(default = "ItemUse::default_consume")]
+    pub consume: usize,                // 每次使用消耗几个，0 为不消耗
+}
+
+impl ItemUse {
+    fn default_consume() -> usize { 1 }
+}
+
+/// 装备。装备期间的属性加成叠加在基础值之上，卸下即消失，不会动到基础值
+#[derive(Debug, Deserialize, Clone)]
+pub struct ItemEquip {
+    pub slot: String,
+    #[serde(default)]
+    pub bonus: HashMap<String, i32>,
+    #[serde(default)]
+    pub travel_time: ValModifier, // 对路程时间的修改，例如自行车为 { Mul = 0.5 }
+}
+
+pub struct ItemSystem {
+    pub items: HashMap<String, ItemDef>,
+    pub capacity: Option<usize>, // 背包总容量，按物品总个数计，None 为不限
+}
+
+impl ItemSystem {
+    pub fn new(items: &Vec<ItemDef>, capacity: Option<usize>) -> Self {
+        let mut item_hash = HashMap::new();
+        for item in items {
+            item_hash.insert(item.name.clone(), item.clone());
+        }
+
+        Self {
+            items: item_hash,
+            capacity,
+        }
+    }
+
+    pub fn displayed_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.items.get(id)
+            .and_then(|def| def.displayed_name.as_deref())
+            .unwrap_or(id)
+    }
+
+    fn equipped<'a>(&'a self, player: &'a Player) -> impl Iterator<Item = &'a ItemEquip> {
+        player.equipment.values()
+            .filter_map(|id| self.items.get(id)?.equip.as_ref())
+    }
+
+    /// 身上所有装备对该属性的加成之和
+    pub fn attribute_bonus(&self, player: &Player, attr: &str) -> i32 {
+        if player.equipment.is_empty() { return 0; } // 绝大多数时候什么都没装备，条件判定里每次取属性都会走到这里
+        self.equipped(player)
+            .filter_map(|equip| equip.bonus.get(attr))
+            .sum()
+    }
+
+    /// 经过装备修正后的路程时间
+    pub fn travel_time(systems: &Systems, player: &Player, time: u32) -> u32 {
+        let mut time = time as i32;
+        for equip in systems.item.equipped(player) {
+            time = equip.travel_time.apply(time, None, systems, player);
+        }
+        time.max(0) as u32
+    }
+
+    /// 背包从 before 变成 after 时，有没有超出堆叠上限和总容量。只管变多的部分，原本就超出的（旧存档之类）不算
+    pub fn check_fits(&self, before: &PlayerItem, after: &PlayerItem) -> anyhow::Result<()> {
+        let count = |items: &PlayerItem, id: &str| items.get(id).map_or(0, |(_, num)| *num);
+        for (id, (_, num)) in after {
+            let max = self.items.get(id).and_then(|def| def.max_stack);
+            if max.is_some_and(|max| *num > max && *num > count(before, id)) {
+                return Err(anyhow::anyhow!("{}最多只能有 {} 个", self.displayed_name(id), max.unwrap()));
+            }
+        }
+        let total = |items: &PlayerItem| items.values().map(|(_, num)| num).sum::<usize>();
+        if self.capacity.is_some_and(|cap| total(after) > cap && total(after) > total(before)) {
+            return Err(anyhow::anyhow!("背包放不下了"));
+        }
+        Ok(())
+    }
+
+    /// 还能放下多少个。给出 id 时同时考虑该物品的堆叠上限；None 为不限。
+    pub fn free_space(&self, items: &PlayerItem, id: Option<&str>) -> Option<usize> {
+        let total = self.capacity.map(|cap|
+            cap.saturating_sub(items.values().map(|(_,num)| num).sum()));
+        let stack = id.and_then(|id| {
+            let max = self.items.get(id)?.max_stack?;
+            Some(max.saturating_sub(items.get(id).map_or(0, |(_,num)| *num)))
+        });
+        match (total, stack) {
+            (Some(total), Some(stack)) => Some(total.min(stack)),
+            (total, stack) => total.or(stack),
+        }
+    }
+
+    /// 实例的实际属性：以定义中的默认属性为底，叠加实例自身的覆盖值。
+    /// 实例的值不是表的话，就视为没有覆盖。
+    pub fn properties(&self, id: &str, overrides: &toml::Value) -> toml::Table {
+        let mut ret = self.items.get(id)
+            .map(|def| def.properties.clone())
+            .unwrap_or_default();
+        if let toml::Value::Table(overrides) = overrides {
+            for (k, v) in overrides {
+                ret.insert(k.clone(), v.clone());
+            }
+        }
+        ret
+    }
+
+    /// 标签既可以写在定义的 tags 里，也可以是（旧写法的）属性表中的一个键。
+    pub fn has_tag(&self, id: &str, overrides: &toml::Value, tag: &str) -> bool {
+        self.items.get(id).is_some_and(|def| def.tags.iter().any(|t| t == tag))
+            || self.properties(id, overrides).contains_key(tag)
+    }
+
+    /// 使用物品，返回需要开始的事件。没有该物品、不可使用或条件不满足时报错，玩家不变
+    pub fn use_item(systems: &Systems, player: &mut Player, id: &str) -> anyhow::Result<Option<String>> {
+        let name = systems.item.displayed_name(id);
+        let Some(usage) = systems.item.items.get(id).and_then(|def| def.usage.as_ref())
+            else { return Err(anyhow::anyhow!("{name}不能使用")); };
+        match player.items.get(id) {
+            None => return Err(anyhow::anyhow!("没有{name}")),
+            Some((_, num)) if *num < usage.consume => return Err(anyhow::anyhow!("{name}不够，需要 {} 个", usage.consume)),
+            _ => (),
+        }
+        if let Some(unmet) = usage.condition.as_ref().and_then(|c| c.explain(systems, player)) {
+            return Err(anyhow::anyhow!("现在不能使用{name}：{unmet}"));
+        }
+        usage.modifier.apply(systems, player)?;
+        if let Some((_,num)) = player.items.get_mut(id) {
+            let before = *num;
+            *num = num.saturating_sub(usage.consume);
+            let after = *num;
+            if after == 0 { player.items.remove(id); }
+            player.ledger.item(player.game_time, id, before, after);
+            // 修改器的变化由 Footprint 记下，用掉的这几个在修改器之外，单独记
+            if after != before { player.changes.push(Change::Item { item: id.to_string(), delta: after as i64 - before as i64 }); }
+        }
+        Ok(usage.jump_to_event.clone())
+    }
+}
@@ -42,7 +42,7 @@ impl MapSystem {
     pub fn travel(
         &self, player: &mut Player,
         to: &str,
-        time_system: &super::time_system::TimeSystem,
+        systems: &super::Systems,
     ) -> Result<()> {
         let current_map = self
             .maps
@@ -50,9 +50,8 @@ impl MapSystem {
             .ok_or(anyhow!("当前地图不存在"))?;
         if let Some(conn) = current_map.connections.iter().find(|c| c.to == to) {
             // 处理旅行时间
-            for _ in 0..conn.time {
-                time_system.update(player);
-            }
+            let minutes = super::item_system::ItemSystem::travel_time(systems, player, conn.time);
+            systems.time.advance(player, chrono::Duration::minutes(minutes as i64));
             player.game_map = to.to_string();
             Ok(())
         } else {
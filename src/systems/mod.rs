@@ -1,14 +1,41 @@
+use craft_system::CraftSystem;
+use item_system::ItemSystem;
 use map_system::MapSystem;
+use script_system::ScriptSystem;
+use shop_system::ShopSystem;
 use time_system::TimeSystem;
 
-use crate::events::{events::EventSystem, triggers::TriggerSystem};
+use crate::{events::{events::EventSystem, triggers::TriggerSystem}, game::GameData};
 
+pub mod craft_system;
+pub mod item_system;
 pub mod map_system;
+pub mod script_system;
+pub mod shop_system;
 pub mod time_system;
 
 pub struct Systems {
     pub time: TimeSystem,
     pub map: MapSystem,
+    pub item: ItemSystem,
+    pub shop: ShopSystem,
+    pub craft: CraftSystem,
+    pub script: ScriptSystem,
     pub trigger: TriggerSystem,
     pub event: EventSystem,
-}
\ No newline at end of file
+}
+
+impl Systems {
+    pub fn new(data: &GameData) -> Self {
+        Self {
+            time: TimeSystem::new(&data.calendar),
+            map: MapSystem::new(&data.maps),
+            item: ItemSystem::new(&data.items, data.inventory_capacity),
+            shop: ShopSystem::new(&data.shops, &data.currency),
+            craft: CraftSystem::new(&data.recipes),
+            script: ScriptSystem::new(&data.script),
+            trigger: TriggerSystem::new(&data.trigger),
+            event: EventSystem::new(&data.events),
+        }
+    }
+}
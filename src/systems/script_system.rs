@@ -0,0 +1,336 @@
+// 脚本。声明式的 TOML 写不下的逻辑（小游戏计分、随机出题之类）交给内嵌的 rhai 脚本。
+// 脚本在读取数据时编译，语法错误当场报出来；运行时只能碰到下面放进作用域的几个变量，
+// 没有文件、网络、时钟之类的能力，并且有执行预算，写出死循环也不会卡住后端线程。
+// print 不输出，debug 打到 stderr。
+//
+// 脚本里可用的变量：
+//   attr      属性名 -> 基础值（不含装备加成）
+//   items     物品 id -> 数量
+//   money     钱
+//   location  所在地图
+//   time      #{ year, month, day, hour, minute, weekday }，weekday 只读
+//   rng       随机数：rng.float() 取 [0, 1)，rng.int(lo, hi) 取闭区间内的整数
+// 作为修改器运行时，脚本结束后这些变量会写回玩家；作为条件或文本时改了也不生效。
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate, Timelike};
+use rhai::packages::{
+    BasicArrayPackage, BasicBlobPackage, BasicMapPackage, BasicMathPackage, BitFieldPackage,
+    CorePackage, LogicPackage, MoreStringPackage, Package,
+};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::{player::Player, rng::GameRng};
+
+use super::Systems;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScriptConfig {
+    #[serde(default = "ScriptConfig::default_max_operations")]
+    pub max_operations: u64, // 每次运行最多执行多少步
+}
+
+impl ScriptConfig {
+    fn default_max_operations() -> u64 { 100_000 }
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self { max_operations: Self::default_max_operations() }
+    }
+}
+
+/// 脚本，数据里直接写源码字符串。读取数据时由 GameData::compile 编译
+#[derive(Clone, Deserialize)]
+#[serde(from = "String")]
+pub struct Script {
+    pub src: String,
+    ast: Option<AST>,
+}
+
+impl From<String> for Script {
+    fn from(src: String) -> Self {
+        Self { src, ast: None }
+    }
+}
+
+impl Script {
+    /// 用 ScriptSystem::engine 编译，和运行时是同一套限制
+    pub fn compile(&mut self, engine: &Engine) -> Result<(), String> {
+        let ast = engine.compile(&self.src)
+            .map_err(|e| format!("脚本语法错误：{e}\n    {}", self.src))?;
+        self.ast = Some(ast);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Script({:?})", self.src)
+    }
+}
+
+pub struct ScriptSystem {
+    engine: Engine,
+}
+
+impl ScriptSystem {
+    pub fn new(config: &ScriptConfig) -> Self {
+        Self { engine: Self::engine(config) }
+    }
+
+    /// 编译和运行都用它
+    pub fn engine(config: &ScriptConfig) -> Engine {
+        // 从空引擎搭起，标准库里只不要读系统时钟的 timestamp()（重放会对不上）。
+        // 空引擎也没有按文件加载模块的 import，print 什么都不做
+        let mut engine = Engine::new_raw();
+        for package in [
+            CorePackage::new().as_shared_module(),
+            BitFieldPackage::new().as_shared_module(),
+            LogicPackage::new().as_shared_module(),
+            BasicMathPackage::new().as_shared_module(),
+            BasicArrayPackage::new().as_shared_module(),
+            BasicBlobPackage::new().as_shared_module(),
+            BasicMapPackage::new().as_shared_module(),
+            MoreStringPackage::new().as_shared_module(),
+        ] {
+            engine.register_global_module(package);
+        }
+        engine.set_max_operations(config.max_operations)
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(10_000)
+            .set_max_array_size(10_000)
+            .set_max_map_size(10_000)
+            .on_debug(|s, _, pos| eprintln!("SCRIPT {pos}: {s}")) // 写数据时调试用，和其他报错一样打到 stderr
+            .register_type_with_name::<GameRng>("Rng")
+            .register_fn("float", |rng: &mut GameRng| rng.next_f64())
+            .register_fn("int", |rng: &mut GameRng, lo: i64, hi: i64| rng.range(lo, hi));
+        engine
+    }
+
+    /// 作为条件运行，须返回 bool
+    pub fn condition(&self, script: &Script, player: &Player) -> Result<bool> {
+        let ret = self.eval(script, &mut Self::scope(player))?;
+        ret.as_bool().map_err(|ty| anyhow!("条件脚本须返回 bool，实际返回 {ty}"))
+    }
+
+    /// 生成文本，返回值转成字符串
+    pub fn text(&self, script: &Script, player: &Player) -> Result<String> {
+        Ok(self.eval(script, &mut Self::scope(player))?.to_string())
+    }
+
+    /// 作为修改器运行。全部检查通过才写回，所以出错时玩家不变
+    pub fn modify(systems: &Systems, script: &Script, player: &mut Player) -> Result<()> {
+        let mut scope = Self::scope(player);
+        let _ = systems.script.eval(script, &mut scope)?; // 修改器只看作用域里的变量，不管返回值
+
+        let get = |name: &str| scope.get_value::<Dynamic>(name).ok_or(anyhow!("脚本删掉了变量 {name}"));
+        let map = |name: &str| get(name)?.try_cast::<Map>().ok_or(anyhow!("脚本把 {name} 改成了别的类型"));
+        let int = |m: &Map, k: &str| m.get(k).and_then(|v| v.as_int().ok())
+            .ok_or(anyhow!("{k} 须为整数"));
+
+        let mut attributes = vec![];
+        for (name, value) in map("attr")? {
+            let Some(def) = player.attribute_defs.get(name.as_str())
+                else { return Err(anyhow!("未知属性 {name}")); };
+            let value = value.as_int().map_err(|_| anyhow!("属性 {name} 须为整数"))?;
+            attributes.push((name.to_string(), value as i32, (value as i32).clamp(def.min, def.max)));
+        }
+
+        let counts = map("items")?;
+        let mut items = player.items.clone();
+        items.retain(|id, _| counts.contains_key(id.as_str()));
+        for (id, count) in counts {
+            let count = count.as_int().map_err(|_| anyhow!("物品 {id} 的数量须为整数"))?;
+            if !systems.item.items.contains_key(id.as_str()) { return Err(anyhow!("未知物品 {id}")); }
+            if count <= 0 {
+                items.remove(id.as_str());
+            } else {
+                items.entry(id.to_string())
+                    .or_insert_with(|| (toml::Value::Table(toml::Table::new()), 0)).1 = count as usize;
+            }
+        }
+        // 和物品修改一样受堆叠上限、背包容量限制。脚本没有溢出策略可选，放不下就整体作废
+        systems.item.check_fits(&player.items, &items)?;
+
+        let money = get("money")?.as_int().map_err(|_| anyhow!("money 须为整数"))? as i32;
+        let location = get("location")?.into_string().map_err(|_| anyhow!("location 须为字符串"))?;
+        if !systems.map.maps.contains_key(&location) { return Err(anyhow!("未知地图 {location}")); }
+
+        let time = map("time")?;
+        let (hour, minute) = (int(&time, "hour")? as u32, int(&time, "minute")? as u32);
+        let game_time = NaiveDate::from_ymd_opt(int(&time, "year")? as i32, int(&time, "month")? as u32, int(&time, "day")? as u32)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .ok_or(anyhow!("脚本给出的时间不合法"))?;
+
+        for (name, unclamped, value) in attributes {
+            let before = std::mem::replace(player.attributes.get_mut(&name).unwrap(), value);
+            player.ledger.attribute(player.game_time, &name, before, unclamped, value);
+        }
+        player.items = items;
+        player.equipment.retain(|_, id| player.items.contains_key(id));
+        player.money = money;
+        player.game_map = location;
+        // 往后拨要经过 advance，跨天的触发器才会触发；往前拨就直接改
+        if game_time > player.game_time {
+            systems.time.advance(player, game_time - player.game_time);
+        } else {
+            player.game_time = game_time;
+        }
+        Ok(())
+    }
+
+    fn eval(&self, script: &Script, scope: &mut Scope) -> Result<Dynamic> {
+        let Some(ast) = &script.ast else { return Err(anyhow!("脚本没有编译：{}", script.src)); };
+        self.engine.eval_ast_with_scope::<Dynamic>(scope, ast).map_err(|e| match *e {
+            rhai::EvalAltResult::ErrorTooManyOperations(_) => anyhow!("脚本超出执行预算：{}", script.src),
+            e => anyhow!("脚本出错：{e}"),
+        })
+    }
+
+    fn scope(player: &Player) -> Scope<'static> {
+        let mut scope = Scope::new();
+        // 每次运行从游戏的随机源里分出一条：脚本里取多少次随机数，游戏的随机源都只走一步。
+        // 只是显示用的运行（段落文字、给玩家看的条件）在 GameRng::preview 里，一步也不走
+        scope.push("rng", GameRng::new(player.rng.next_u64()));
+        scope.push("attr", player.attributes.iter()
+            .map(|(k, v)| (k.into(), Dynamic::from_int(*v as i64)))
+            .collect::<Map>());
+        scope.push("items", player.items.iter()
+            .map(|(k, (_, num))| (k.into(), Dynamic::from_int(*num as i64)))
+            .collect::<Map>());
+        scope.push("money", player.money as i64);
+        scope.push("location", player.game_map.clone());
+        let time = player.game_time;
+        scope.push("time", Map::from_iter([
+            ("year".into(), Dynamic::from_int(time.year() as i64)),
+            ("month".into(), Dynamic::from_int(time.month() as i64)),
+            ("day".into(), Dynamic::from_int(time.day() as i64)),
+            ("hour".into(), Dynamic::from_int(time.hour() as i64)),
+            ("minute".into(), Dynamic::from_int(time.minute() as i64)),
+            ("weekday".into(), time.weekday().to_string().into()),
+        ]));
+        scope
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{game::GameData, testing};
+
+    const TEA: &str = r#"
+[script]
+max_operations = 1000
+
+[[items]]
+name = "tea"
+max_stack = 3
+"#;
+
+    fn compiled(src: &str) -> Script {
+        let mut script = Script::from(src.to_string());
+        script.compile(&ScriptSystem::engine(&testing::data(TEA).script)).unwrap();
+        script
+    }
+
+    fn condition(src: &str) -> Result<bool> {
+        let (_, systems, player) = testing::setup(TEA);
+        systems.script.condition(&compiled(src), &player)
+    }
+
+    /// 玩家身上能被脚本改到的部分
+    fn state(player: &Player) -> String {
+        let mut items: Vec<_> = player.items.iter().map(|(id, (_, num))| (id, num)).collect();
+        items.sort();
+        format!("{:?} {items:?} {} {} {}", player.attributes.val, player.money, player.game_map, player.game_time)
+    }
+
+    /// 作为修改器运行，返回结果和运行前后的玩家
+    fn modify(src: &str) -> (Result<()>, Player, Player) {
+        let (_, systems, mut player) = testing::setup(TEA);
+        player.items.insert("tea".into(), (toml::Value::Table(Default::default()), 2));
+        let before = player.clone();
+        let ret = ScriptSystem::modify(&systems, &compiled(src), &mut player);
+        (ret, before, player)
+    }
+
+    #[test]
+    fn operation_budget() {
+        assert!(condition("let x = 0; for i in 0..10 { x += i } x == 45").unwrap());
+        let err = condition("loop {}").unwrap_err().to_string();
+        assert!(err.contains("超出执行预算"), "{err}");
+        // 修改器超出预算，玩家不变
+        let (ret, before, after) = modify("attr.energy = 50; loop {}");
+        assert!(ret.is_err());
+        assert_eq!(state(&before), state(&after));
+    }
+
+    #[test]
+    fn compiled_with_the_runtime_limits() {
+        // 字符串长度等限制在编译时就检查，读数据用的引擎必须和运行时一样
+        let long = format!("`{}`", "字".repeat(20_000));
+        assert!(Engine::new_raw().compile(&long).is_ok());
+        assert!(Script::from(long.clone()).compile(&ScriptSystem::engine(&ScriptConfig::default())).is_err());
+        let extra = format!("[[events]]\nname = \"long\"\npriority = 1\nforce = false\ncondition = {{ type = \"Script\", script = \"{long} == ``\" }}\nsegments = []");
+        let mut data: GameData = toml::from_str(&testing::source(&extra)).unwrap();
+        let err = data.compile().unwrap_err().to_string();
+        assert!(err.contains("事件 long") && err.contains("脚本语法错误"), "{err}");
+        // 没编译的脚本不会被悄悄跑掉
+        let (_, systems, player) = testing::setup("");
+        assert!(systems.script.condition(&Script::from("true".to_string()), &player).is_err());
+    }
+
+    #[test]
+    fn sandbox() {
+        assert!(condition("import \"main\" as m; true").is_err()); // 不能按文件加载模块
+        assert!(condition("timestamp(); true").is_err());          // 不能读系统时钟
+        assert!(condition("player.money > 0").is_err());           // 只有放进作用域的变量
+        assert!(condition("print(\"hi\"); true").unwrap());        // print 什么都不做
+        assert!(condition("attr.energy == 15 && items.len() == 0 && time.year == 2024").unwrap());
+        let err = condition("1").unwrap_err().to_string();
+        assert!(err.contains("须返回 bool"), "{err}");
+    }
+
+    #[test]
+    fn write_back() {
+        // 属性夹在上下限之间，账本记下夹之前的值
+        let (ret, _, after) = modify("attr.health = 150; attr.energy -= 100;");
+        ret.unwrap();
+        assert_eq!((after.attributes.get("health"), after.attributes.get("energy")), (Some(&100), Some(&0)));
+        let health = after.ledger.entries.iter().find(|e| e.name == "health").unwrap();
+        assert_eq!((health.before, health.unclamped, health.after), (80, 150, 100));
+
+        // 物品：数量为 0 就删掉，新物品按定义加进来
+        let (ret, _, after) = modify("items.tea = 0; items.coffee = 2; money += 10; time.hour += 2;");
+        ret.unwrap();
+        assert!(!after.items.contains_key("tea"));
+        assert_eq!(after.items["coffee"].1, 2);
+        assert_eq!(after.money, 10);
+        assert_eq!(after.game_time.to_string(), "2024-01-01 02:00:00");
+
+        // 超过堆叠上限、未知的东西、改了类型：整体作废，玩家不变
+        for src in [
+            "attr.energy = 50; items.tea = 4;",
+            "attr.energy = 50; items.nope = 1;",
+            "attr.energy = 50; attr.nope = 1;",
+            "attr.energy = 50; location = \"Nowhere\";",
+            "attr.energy = 50; money = \"很多\";",
+            "attr.energy = 50; time.month = 13;",
+            "attr.energy = 50; items = 1;",
+        ] {
+            let (ret, before, after) = modify(src);
+            assert!(ret.is_err(), "{src}");
+            assert_eq!(state(&before), state(&after), "{src}");
+            assert!(after.ledger.entries.is_empty(), "{src}");
+        }
+        // 堆叠上限只拦加多，原本就超的不强制减
+        let (ret, _, after) = modify("items.tea = 3;");
+        ret.unwrap();
+        assert_eq!(after.items["tea"].1, 3);
+    }
+}
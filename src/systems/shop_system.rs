@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{events::conditions::TimeCondition, player::Player};
+
+use super::Systems;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Currency {
+    #[serde(default = "Currency::default_name")]
+    pub name: String,
+    #[serde(default)]
+    pub initial: i32,
+}
+
+impl Currency {
+    fn default_name() -> String { "元".into() }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self { name: Self::default_name(), initial: 0 }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Goods {
+    pub item: String,
+    pub price: i32,
+    pub stock: Option<usize>,    // 库存，None 为不限
+    pub sell_price: Option<i32>, // 回收价，None 为不回收
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ShopDef {
+    pub name: String,
+    pub displayed_name: Option<String>,
+    pub location: Option<String>,     // 在哪张地图上能逛；None 则只能通过事件交易
+    pub hours: Option<TimeCondition>, // 营业时间，None 为全天
+    #[serde(default)]
+    pub goods: Vec<Goods>,
+}
+
+pub struct ShopSystem {
+    pub shops: HashMap<String, ShopDef>,
+    pub currency: Currency,
+}
+
+impl ShopSystem {
+    pub fn new(shops: &Vec<ShopDef>, currency: &Currency) -> Self {
+        let mut shop_hash = HashMap::new();
+        for shop in shops {
+            shop_hash.insert(shop.name.clone(), shop.clone());
+        }
+
+        Self {
+            shops: shop_hash,
+            currency: currency.clone(),
+        }
+    }
+
+    pub fn displayed_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.shops.get(id)
+            .and_then(|shop| shop.displayed_name.as_deref())
+            .unwrap_or(id)
+    }
+
+    pub fn is_open(systems: &Systems, player: &Player, shop: &ShopDef) -> bool {
+        shop.location.as_ref().is_some_and(|loc| loc == &player.game_map)
+            && shop.hours.as_ref().is_none_or(|hours| systems.time.check_condition(player, hours))
+    }
+
+    /// 玩家现在能逛的商店
+    pub fn open_shops<'a>(systems: &'a Systems, player: &'a Player) -> impl Iterator<Item = &'a ShopDef> {
+        systems.shop.shops.values().filter(|shop| Self::is_open(systems, player, shop))
+    }
+
+    pub fn stock_left(player: &Player, shop: &ShopDef, goods: &Goods) -> Option<usize> {
+        let sold = player.shop_sold.get(&shop.name)
+            .and_then(|sold| sold.get(&goods.item))
+            .copied().unwrap_or(0);
+        goods.stock.map(|stock| stock.saturating_sub(sold))
+    }
+
+    fn goods<'a>(&'a self, shop: &str, item: &str) -> Result<(&'a ShopDef, &'a Goods)> {
+        let shop = self.shops.get(shop).ok_or(anyhow!("未知商店 {shop}"))?;
+        let goods = shop.goods.iter().find(|goods| goods.item == item)
+            .ok_or(anyhow!("{} 没有 {item}", self.displayed_name(&shop.name)))?;
+        Ok((shop, goods))
+    }
+
+    pub fn price(&self, shop: &str, item: &str) -> Option<i32> {
+        self.goods(shop, item).ok().map(|(_, goods)| goods.price)
+    }
+
+    /// 买。先检查库存、钱和背包空间，全部通过才动手，所以要么全成要么什么都不变。
+    pub fn buy(systems: &Systems, player: &mut Player, shop: &str, item: &str, count: usize) -> Result<()> {
+        let (shop, goods) = systems.shop.goods(shop, item)?;
+        if Self::stock_left(player, shop, goods).is_some_and(|left| left < count) {
+            return Err(anyhow!("库存不足"));
+        }
+        let cost = goods.price * count as i32;
+        if player.money < cost {
+            return Err(anyhow!("{}不够", systems.shop.currency.name));
+        }
+        if systems.item.free_space(&player.items, Some(item)).is_some_and(|space| space < count) {
+            return Err(anyhow!("放不下了"));
+        }
+
+        player.money -= cost;
+        player.items.entry(item.to_string())
+            .or_insert_with(|| (toml::Value::Table(toml::Table::new()), 0))
+            .1 += count;
+        *player.shop_sold.entry(shop.name.clone()).or_default()
+            .entry(item.to_string()).or_default() += count;
+        Ok(())
+    }
+
+    /// 卖。同样先检查再动手。
+    pub fn sell(systems: &Systems, player: &mut Player, shop: &str, item: &str, count: usize) -> Result<()> {
+        let (shop, goods) = systems.shop.goods(shop, item)?;
+        let price = goods.sell_price
+            .ok_or(anyhow!("{} 不收 {item}", systems.shop.displayed_name(&shop.name)))?;
+        let Some((_, num)) = player.items.get_mut(item).filter(|(_, num)| *num >= count)
+            else { return Err(anyhow!("没有那么多可卖")); };
+
+        *num -= count;
+        if *num == 0 {
+            player.items.remove(item);
+            player.equipment.retain(|_, id| id != item);
+        }
+        player.money += price * count as i32;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const CANTEEN: &str = r#"
+[[items]]
+name = "tea"
+max_stack = 3
+
+[[shops]]
+name = "canteen"
+location = "Town"
+goods = [
+    { item = "tea", price = 5, stock = 4, sell_price = 3 },
+    { item = "coffee", price = 10 },
+]
+"#;
+
+    /// 买卖会动到的部分
+    fn state(player: &Player) -> String {
+        let mut items: Vec<_> = player.items.iter().map(|(id, (_, num))| (id, num)).collect();
+        items.sort();
+        format!("{} {items:?} {:?}", player.money, player.shop_sold)
+    }
+
+    #[test]
+    fn buy_and_sell() {
+        let (_, systems, mut player) = testing::setup(CANTEEN);
+        player.money = 100;
+        ShopSystem::buy(&systems, &mut player, "canteen", "tea", 2).unwrap();
+        ShopSystem::buy(&systems, &mut player, "canteen", "coffee", 3).unwrap();
+        assert_eq!(player.money, 60);
+        assert_eq!(player.items["tea"].1, 2);
+        assert_eq!(ShopSystem::stock_left(&player, &systems.shop.shops["canteen"], &systems.shop.shops["canteen"].goods[0]), Some(2));
+        ShopSystem::sell(&systems, &mut player, "canteen", "tea", 2).unwrap();
+        assert_eq!(player.money, 66);
+        assert!(!player.items.contains_key("tea")); // 卖光了就没有这一项
+    }
+
+    #[test]
+    fn failures_change_nothing() {
+        let (_, systems, mut player) = testing::setup(CANTEEN);
+        player.money = 25;
+        ShopSystem::buy(&systems, &mut player, "canteen", "tea", 2).unwrap();
+        let before = state(&player);
+        let buy = |player: &mut Player, item: &str, count| ShopSystem::buy(&systems, player, "canteen", item, count);
+        for (ret, expected) in [
+            (buy(&mut player, "tea", 3), "库存不足"),      // 只剩 2 份
+            (buy(&mut player, "coffee", 2), "元不够"),
+            (buy(&mut player, "tea", 2), "放不下了"),      // 最多 3 杯
+            (buy(&mut player, "health", 1), "canteen 没有 health"),
+            (ShopSystem::buy(&systems, &mut player, "nowhere", "tea", 1), "未知商店 nowhere"),
+            (ShopSystem::sell(&systems, &mut player, "canteen", "coffee", 1), "canteen 不收 coffee"),
+            (ShopSystem::sell(&systems, &mut player, "canteen", "tea", 3), "没有那么多可卖"),
+        ] {
+            assert_eq!(ret.unwrap_err().to_string(), expected);
+            assert_eq!(state(&player), before, "{expected}");
+        }
+    }
+}
@@ -1,19 +1,69 @@
-use crate::{events::conditions::TimeCondition, player::Player};
-use chrono::NaiveDateTime;
+use crate::{events::{conditions::TimeCondition, schedule::Pending, triggers::Trigger}, player::Player};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
+use serde::Deserialize;
 
-pub struct TimeSystem;
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Calendar {
+    pub term_start: Option<NaiveDate>, // 开学日期，教学周从这一周算起
+}
+
+/// 时间条件里没满足的那一项
+enum TimePart { Range, Days, Dates, Months, MonthDays, Weeks, Every, Times }
+
+pub struct TimeSystem {
+    pub calendar: Calendar,
+}
 
 impl TimeSystem {
-    pub fn new() -> Self { Self }
+    pub fn new(calendar: &Calendar) -> Self { Self { calendar: calendar.clone() } }
     pub fn init_time() -> NaiveDateTime {
         // 初始化当前时间，可以根据需要调整
         NaiveDateTime::parse_from_str("2024-01-02 07:00", "%Y-%m-%d %H:%M")
             .unwrap()
     }
 
-    pub fn update(&self, _player: &mut Player) {
-        // 更新时间逻辑，例如每回合增加一定时间
-        // self.current_time = self.current_time + chrono::Duration::minutes(1);
+    /// 走一分钟
+    pub fn update(&self, player: &mut Player) {
+        self.advance(player, Duration::minutes(1));
+    }
+
+    /// 时间往前走。跨过午夜触发 NewDay，跨进新的一周再触发 NewWeek；一下跨了好几天也只各触发一次
+    pub fn advance(&self, player: &mut Player, by: Duration) {
+        if by <= Duration::zero() { return; }
+        let before = player.game_time.date();
+        player.game_time += by;
+        let after = player.game_time.date();
+        if after > before {
+            player.trigger.insert(Trigger::NewDay);
+            let monday = |date: NaiveDate| date - Duration::days(date.weekday().num_days_from_monday() as i64);
+            if monday(after) > monday(before) { player.trigger.insert(Trigger::NewWeek); }
+        }
+    }
+
+    /// 放出到点的触发器。到点的事件先留在队列里，等手头没有事件时由 next_due_event 取出
+    pub fn release(&self, player: &mut Player) {
+        let now = player.game_time;
+        let due = player.scheduled.partition_point(|s| s.at <= now);
+        let mut kept = vec![];
+        for scheduled in player.scheduled.drain(..due) {
+            match scheduled.what {
+                Pending::Trigger { trigger } => { player.trigger.insert(trigger); },
+                Pending::Event { .. } => kept.push(scheduled),
+            }
+        }
+        player.scheduled.splice(0..0, kept);
+    }
+
+    /// 取出最早到点的事件
+    pub fn next_due_event(&self, player: &mut Player) -> Option<String> {
+        let now = player.game_time;
+        let i = player.scheduled.iter()
+            .take_while(|s| s.at <= now)
+            .position(|s| matches!(s.what, Pending::Event { .. }))?;
+        match player.scheduled.remove(i).what {
+            Pending::Event { event } => Some(event),
+            Pending::Trigger { .. } => unreachable!(),
+        }
     }
 
     pub fn get_current_time(player: &Player) -> NaiveDateTime {
@@ -21,47 +71,265 @@ impl TimeSystem {
     }
 
     pub fn check_condition(&self, player: &Player, condition: &TimeCondition) -> bool {
-        let current_time: NaiveDateTime = player.game_time;
+        self.first_unmet(player, condition).is_none()
+    }
+
+    /// 不满足时返回给玩家看的原因
+    pub fn check(&self, player: &Player, condition: &TimeCondition) -> Result<(), String> {
+        let Some(unmet) = self.first_unmet(player, condition) else { return Ok(()); };
+        let list = |names: Vec<String>| format!("只在 {}", names.join("、"));
+        Err(match unmet {
+            TimePart::Range => {
+                let (start, end) = Self::range(condition);
+                format!("只在 {}-{} 之间", start.format("%H:%M"), end.format("%H:%M"))
+            },
+            TimePart::Days => list(condition.days.iter().flatten().map(|day| Self::weekday_name(*day).to_string()).collect()),
+            TimePart::Dates => {
+                let (from, to) = condition.dates.unwrap();
+                format!("只在 {from} 到 {to} 之间")
+            },
+            TimePart::Months => list(condition.months.iter().flatten().map(|month| format!("{month} 月")).collect()),
+            TimePart::MonthDays => list(condition.month_days.iter().flatten().map(|d| format!("{} 月 {} 日", d.0, d.1)).collect()),
+            TimePart::Weeks => {
+                let weeks: Vec<String> = condition.weeks.iter().flatten().map(|week| week.to_string()).collect();
+                format!("只在第 {} 周", weeks.join("、"))
+            },
+            TimePart::Every => format!("每 {} 天才有一次", condition.every.as_ref().unwrap().days),
+            TimePart::Times => list(condition.times.iter().flatten().map(|t| t.0.format("%H:%M").to_string()).collect()),
+        })
+    }
 
-        // 检查星期
-        let day_str = chrono::Datelike::weekday(&current_time).to_string();
-        let day_match = condition
-            .days
-            .iter()
-            .any(|day| day.eq_ignore_ascii_case(&day_str));
+    fn range(condition: &TimeCondition) -> (NaiveTime, NaiveTime) {
+        (condition.start.map_or(NaiveTime::MIN, |c| c.0),
+            condition.end.map_or(NaiveTime::from_hms_opt(23, 59, 59).unwrap(), |c| c.0))
+    }
 
-        if !day_match {
-            return false;
+    /// 第一项不满足的要求。判定走得很勤，这里不拼说明文字，要说明时见 check
+    fn first_unmet(&self, player: &Player, condition: &TimeCondition) -> Option<TimePart> {
+        let now = player.game_time;
+        let time = now.time().with_second(0).unwrap();
+        let (start, end) = Self::range(condition);
+
+        // 检查时间范围。跨夜时过了午夜的部分算前一天
+        let overnight = start > end;
+        let in_range = if overnight { time >= start || time <= end } else { start <= time && time <= end };
+        if !in_range { return Some(TimePart::Range); }
+        let date = if overnight && time <= end { now.date() - Duration::days(1) } else { now.date() };
+
+        // 检查星期与日期
+        if condition.days.as_ref().is_some_and(|days| !days.contains(&date.weekday())) {
+            return Some(TimePart::Days);
+        }
+        if condition.dates.is_some_and(|(from, to)| date < from || date > to) {
+            return Some(TimePart::Dates);
+        }
+        if condition.months.as_ref().is_some_and(|months| !months.contains(&date.month())) {
+            return Some(TimePart::Months);
+        }
+        if condition.month_days.as_ref()
+            .is_some_and(|days| !days.iter().any(|d| d.0 == date.month() && d.1 == date.day())) {
+                return Some(TimePart::MonthDays);
+        }
+        if condition.weeks.as_ref()
+            .is_some_and(|weeks| !self.week_of_term(date).is_some_and(|week| weeks.contains(&week))) {
+                return Some(TimePart::Weeks);
+        }
+        if let Some(every) = &condition.every {
+            let from = every.from.or(self.calendar.term_start);
+            if from.is_none_or(|from| (date - from).num_days().rem_euclid(every.days) != 0) {
+                return Some(TimePart::Every);
+            }
         }
 
-        // 检查时间范围
-        let start_time = NaiveDateTime::parse_from_str(
-            &format!("{} {}", current_time.date(), condition.start),
-            "%Y-%m-%d %H:%M",
-        )
-        .unwrap();
-        let end_time = NaiveDateTime::parse_from_str(
-            &format!("{} {}", current_time.date(), condition.end),
-            "%Y-%m-%d %H:%M",
-        )
-        .unwrap();
-
-        let in_range = current_time >= start_time && current_time <= end_time;
-
-        // 检查具体时间点
-        let times_match = if let Some(times) = &condition.times {
-            times.iter().any(|t| {
-                let specific_time = NaiveDateTime::parse_from_str(
-                    &format!("{} {}", current_time.date(), t),
-                    "%Y-%m-%d %H:%M",
-                )
-                .unwrap();
-                current_time == specific_time
-            })
-        } else {
-            true
+        // 检查具体时间点。时间是跳着走的（选项耗时、等待、赶路），所以上一轮挑事件以来跨过的都算；
+        // 时间没动时按分钟比较
+        let crossed = |t: NaiveTime| match player.last_checked.filter(|last| *last < now) {
+            Some(last) => {
+                let at = now.date().and_time(t);
+                let at = if at > now { at - Duration::days(1) } else { at };
+                at > last
+            },
+            None => t == time,
         };
+        condition.times.as_ref()
+            .filter(|times| !times.iter().any(|t| crossed(t.0)))
+            .map(|_| TimePart::Times)
+    }
+
+    pub fn weekday_name(day: Weekday) -> &'static str {
+        ["周一", "周二", "周三", "周四", "周五", "周六", "周日"][day.num_days_from_monday() as usize]
+    }
+
+    /// 教学周，开学那一周为第 1 周，开学前为 0 及负数
+    pub fn week_of_term(&self, date: NaiveDate) -> Option<i64> {
+        let start = self.calendar.term_start?;
+        let monday = start - Duration::days(start.weekday().num_days_from_monday() as i64);
+        Some((date - monday).num_days().div_euclid(7) + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{events::schedule::Scheduled, testing};
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// 开学 2024-02-26（周一）。last 为上一轮挑事件的时间
+    fn met_since(cond: &str, now: &str, last: Option<&str>) -> bool {
+        let time = TimeSystem::new(&Calendar { term_start: NaiveDate::from_ymd_opt(2024, 2, 26) });
+        let (_, _, mut player) = testing::setup("");
+        player.game_time = at(now);
+        player.last_checked = last.map(at);
+        time.check_condition(&player, &toml::from_str(cond).unwrap())
+    }
+
+    fn met(cond: &str, now: &str) -> bool { met_since(cond, now, None) }
+
+    #[test]
+    fn overnight_range_counts_as_previous_day() {
+        let fri_night = "start = '22:00'\nend = '02:00'\ndays = ['Fri']";
+        assert!(met(fri_night, "2024-03-01 23:00"));
+        assert!(met(fri_night, "2024-03-02 01:30")); // 周六凌晨算周五
+        assert!(met(fri_night, "2024-03-02 02:00"));
+        assert!(!met(fri_night, "2024-03-02 02:01"));
+        assert!(!met(fri_night, "2024-03-02 23:00"));
+        assert!(!met(fri_night, "2024-03-01 01:00")); // 周五凌晨算周四
+        assert!(!met(fri_night, "2024-03-01 12:00"));
+        // 教学周、日期也按前一天算：03-04 是第 2 周的周一，凌晨还算第 1 周
+        let week1 = "start = '22:00'\nend = '02:00'\nweeks = [1]";
+        assert!(met(week1, "2024-03-04 01:00"));
+        assert!(!met(week1, "2024-03-04 23:00"));
+        let feb = "start = '22:00'\nend = '02:00'\ndates = ['2024-02-01', '2024-02-29']";
+        assert!(met(feb, "2024-03-01 00:30"));
+        assert!(!met(feb, "2024-03-01 22:30"));
+        // 不跨夜的范围两端都含
+        let day = "start = '08:00'\nend = '17:00'";
+        assert!(met(day, "2024-03-01 08:00") && met(day, "2024-03-01 17:00"));
+        assert!(!met(day, "2024-03-01 07:59"));
+    }
+
+    #[test]
+    fn every_n_days() {
+        let every3 = "every = { days = 3 }";
+        assert!(met(every3, "2024-02-26 10:00"));
+        assert!(!met(every3, "2024-02-27 10:00"));
+        assert!(met(every3, "2024-02-29 10:00"));
+        assert!(met(every3, "2024-02-23 10:00")); // 开学前往回数也对得上
+        let from = "every = { days = 3, from = '2024-03-02' }";
+        assert!(met(from, "2024-03-05 10:00"));
+        assert!(!met(from, "2024-03-04 10:00"));
+        assert!(met(from, "2024-02-28 10:00"));
+        // 没有 from 也没有开学日期，永远不满足
+        let time = TimeSystem::new(&Calendar::default());
+        let (_, _, mut player) = testing::setup("");
+        player.game_time = at("2024-02-26 10:00");
+        assert!(!time.check_condition(&player, &toml::from_str(every3).unwrap()));
+        assert!(!time.check_condition(&player, &toml::from_str("weeks = [1]").unwrap()));
+    }
+
+    #[test]
+    fn calendar_parts() {
+        assert!(met("weeks = [2, 3]", "2024-03-04 00:00"));
+        assert!(met("weeks = [2, 3]", "2024-03-17 23:59"));
+        assert!(!met("weeks = [2, 3]", "2024-03-03 12:00"));
+        assert!(met("weeks = [0]", "2024-02-25 12:00"));
+        assert!(met("months = [3, 9]", "2024-03-15 12:00"));
+        assert!(!met("months = [3, 9]", "2024-04-15 12:00"));
+        assert!(met("month_days = ['03-01', '12-25']", "2024-12-25 12:00"));
+        assert!(!met("month_days = ['03-01', '12-25']", "2024-03-02 12:00"));
+        assert!(met("month_days = ['02-29']", "2024-02-29 12:00"));
+        assert!(met("dates = ['2024-03-01', '2024-03-03']", "2024-03-03 23:59"));
+        assert!(!met("dates = ['2024-03-01', '2024-03-03']", "2024-03-04 00:00"));
+        assert!(met("days = ['Sat', 'Sunday']", "2024-03-03 12:00"));
+        assert!(!met("days = ['Sat', 'Sunday']", "2024-03-04 12:00"));
+    }
+
+    #[test]
+    fn times_crossed_since_last_check() {
+        let eight = "times = ['08:00', '20:00']";
+        // 时间没动或者没有上一轮：按分钟比较
+        assert!(met(eight, "2024-03-01 08:00"));
+        assert!(!met(eight, "2024-03-01 08:01"));
+        assert!(met_since(eight, "2024-03-01 08:00", Some("2024-03-01 08:00")));
+        // 一下走过了 08:00
+        assert!(met_since(eight, "2024-03-01 08:30", Some("2024-03-01 07:50")));
+        assert!(met_since(eight, "2024-03-01 08:00", Some("2024-03-01 07:59")));
+        // 上一轮正好在 08:00，已经算过了
+        assert!(!met_since(eight, "2024-03-01 08:30", Some("2024-03-01 08:00")));
+        assert!(!met_since(eight, "2024-03-01 19:00", Some("2024-03-01 09:00")));
+        // 跨过午夜
+        let late = "times = ['23:30']";
+        assert!(met_since(late, "2024-03-02 00:30", Some("2024-03-01 23:00")));
+        assert!(!met_since(late, "2024-03-02 00:30", Some("2024-03-01 23:45")));
+        // 睡了一整夜：最近一次 08:00 在上一轮之前
+        assert!(!met_since(eight, "2024-03-02 07:00", Some("2024-03-01 21:00")));
+        assert!(met_since(eight, "2024-03-02 08:10", Some("2024-03-01 21:00")));
+    }
+
+    #[test]
+    fn unmet_reason() {
+        let time = TimeSystem::new(&Calendar::default());
+        let (_, _, mut player) = testing::setup("");
+        player.game_time = at("2024-03-01 12:00");
+        let check = |cond: &str| time.check(&player, &toml::from_str(cond).unwrap());
+        assert_eq!(check("start = '22:00'\nend = '02:00'"), Err("只在 22:00-02:00 之间".into()));
+        assert_eq!(check("days = ['Mon', 'Wed']"), Err("只在 周一、周三".into()));
+        assert_eq!(check("every = { days = 2, from = '2024-02-29' }"), Err("每 2 天才有一次".into()));
+        assert_eq!(check("days = ['Fri']"), Ok(()));
+    }
+
+    fn event(name: &str) -> Pending { Pending::Event { event: name.into() } }
+    fn custom(name: &str) -> Pending { Pending::Trigger { trigger: Trigger::Custom(name.into()) } }
+
+    #[test]
+    fn schedule_releases_in_order() {
+        let time = TimeSystem::new(&Calendar::default());
+        let (_, _, mut player) = testing::setup("");
+        player.game_time = at("2024-03-01 12:00");
+        player.schedule(at("2024-03-03 08:00"), event("exam"));
+        player.schedule(at("2024-03-02 08:00"), event("a"));
+        player.schedule(at("2024-03-02 08:00"), custom("bell"));
+        player.schedule(at("2024-03-02 08:00"), event("b")); // 同一时刻按安排的先后
+        player.schedule(at("2024-03-01 18:00"), custom("dinner"));
+        let order: Vec<_> = player.scheduled.iter().map(|s| (s.at, s.what.clone())).collect();
+        assert_eq!(order, [
+            (at("2024-03-01 18:00"), custom("dinner")),
+            (at("2024-03-02 08:00"), event("a")),
+            (at("2024-03-02 08:00"), custom("bell")),
+            (at("2024-03-02 08:00"), event("b")),
+            (at("2024-03-03 08:00"), event("exam")),
+        ]);
+
+        // 没到点什么都不放
+        time.release(&mut player);
+        assert_eq!(time.next_due_event(&mut player), None);
+        assert_eq!(player.scheduled.len(), 5);
+        assert!(!player.trigger.contains(&Trigger::Custom("dinner".into())));
+
+        // 到点的触发器一次放完，事件留在队列里一个一个取
+        player.game_time = at("2024-03-02 09:00");
+        time.release(&mut player);
+        assert!(player.trigger.contains(&Trigger::Custom("dinner".into())));
+        assert!(player.trigger.contains(&Trigger::Custom("bell".into())));
+        assert_eq!(player.scheduled.len(), 3);
+        assert_eq!(time.next_due_event(&mut player).as_deref(), Some("a"));
+        assert_eq!(time.next_due_event(&mut player).as_deref(), Some("b"));
+        assert_eq!(time.next_due_event(&mut player), None); // exam 还没到
+        assert_eq!(player.scheduled.len(), 1);
+    }
 
-        in_range && times_match
+    #[test]
+    fn schedule_survives_save() {
+        let (_, _, mut player) = testing::setup("");
+        player.schedule(at("2024-03-02 08:00"), event("exam"));
+        player.schedule(at("2024-03-01 18:00"), custom("dinner"));
+        let saved = toml::Value::try_from(&player.scheduled).unwrap().to_string();
+        let loaded: Vec<Scheduled> = toml::from_str::<toml::Table>(&format!("scheduled = {saved}")).unwrap()
+            ["scheduled"].clone().try_into().unwrap();
+        let what = |queue: &[Scheduled]| queue.iter().map(|s| (s.at, s.what.clone())).collect::<Vec<_>>();
+        assert_eq!(what(&loaded), what(&player.scheduled));
     }
 }
@@ -0,0 +1,74 @@
+// testing.rs
+// 单元测试共用的数据与搭建。DATA 是一份能读进来的最小数据，
+// 测试要用别的定义时写成 extra 接在后面（须以表头开始）；extra 里没有 [[events]] 就补一个空的。
+
+use crate::{events::modifier::Modifier, game::GameData, player::Player, systems::{script_system::ScriptSystem, Systems}};
+
+pub const DATA: &str = r#"
+[protagonist]
+name = "小科"
+gender = "男"
+
+[[player]]
+name = "health"
+max = 100
+min = 0
+default = 80
+over_max = 100
+under_min = 0
+over_max_desc = ""
+under_min_desc = ""
+
+[[player]]
+name = "energy"
+max = 100
+min = 0
+default = 15
+over_max = 100
+under_min = 0
+over_max_desc = ""
+under_min_desc = ""
+
+[[maps]]
+name = "Town"
+displayed_name = "小镇"
+connections = []
+
+[[items]]
+name = "coffee"
+displayed_name = "咖啡"
+"#;
+
+/// DATA 接上 extra 的完整数据文本
+pub fn source(extra: &str) -> String {
+    let events = if extra.contains("[[events]]") { "" } else { "events = []" };
+    format!("{events}\n{DATA}\n{extra}")
+}
+
+/// DATA 接上 extra 读进来，和开局时一样展开具名定义、编译
+pub fn data(extra: &str) -> GameData {
+    let mut data: GameData = toml::from_str(&source(extra)).unwrap_or_else(|e| panic!("测试数据有误：{e}"));
+    data.resolve().and_then(|_| data.compile()).unwrap_or_else(|e| panic!("测试数据有误：{e}"));
+    data
+}
+
+/// 按数据新开局的玩家
+pub fn player(data: &GameData) -> Player {
+    Player::new(&data.player, &data.currency, &data.protagonist)
+}
+
+pub fn setup(extra: &str) -> (GameData, Systems, Player) {
+    let data = data(extra);
+    let systems = Systems::new(&data);
+    let player = player(&data);
+    (data, systems, player)
+}
+
+/// 单独读一条修改，和数据里的一样编译好
+pub fn modifier(data: &GameData, src: &str) -> Modifier {
+    let mut modifier: Modifier = toml::from_str(src).unwrap_or_else(|e| panic!("修改写错了：{e}"));
+    modifier.compile(&data.player.iter().map(|attr| attr.name.clone()).collect::<Vec<_>>());
+    let engine = ScriptSystem::engine(&data.script);
+    for script in modifier.scripts() { script.compile(&engine).unwrap(); }
+    modifier
+}
@@ -0,0 +1,39 @@
+// 变化提示。选项、物品造成的变化（健康 -10、获得 咖啡 ×1……）在右上角停留几秒后消失。
+
+use egui::{Color32, Context};
+
+use crate::{events::changes::Change, frontend::markup, MainApp};
+
+const SHOW_SECONDS: f64 = 4.;
+
+#[derive(Default)]
+pub struct ToastCache {
+    toasts: Vec<(String, Color32, f64)>, // 文本、颜色、消失的时刻
+}
+
+pub fn toasts(app: &mut MainApp, ctx: &Context) {
+    let now = ctx.input(|i| i.time);
+    let cache = &mut app.toast_cache;
+    for view in app.backend.cache.changes.take().unwrap_or_default() {
+        let color = match view.change {
+            Change::Attribute { delta, .. } | Change::Money { delta } if delta < 0 => Color32::LIGHT_RED,
+            Change::Item { delta, .. } if delta < 0 => Color32::LIGHT_RED,
+            Change::Attribute { .. } | Change::Money { .. } | Change::Item { .. } => Color32::LIGHT_GREEN,
+            Change::Location { .. } | Change::Time { .. } => Color32::LIGHT_GRAY,
+        };
+        // 物品名、地名里可能带标记，提示只有一种颜色，只留文字
+        cache.toasts.push((markup::plain(&view.text), color, now + SHOW_SECONDS));
+    }
+    cache.toasts.retain(|(_, _, until)| *until > now);
+    if cache.toasts.is_empty() { return; }
+
+    egui::Area::new(egui::Id::new("toasts"))
+        .anchor(egui::Align2::RIGHT_TOP, [-12., 12.])
+        .interactable(false)
+        .show(ctx, |ui| {
+            for (text, color, _) in &cache.toasts {
+                egui::Frame::popup(ui.style()).show(ui, |ui| { ui.colored_label(*color, text); });
+            }
+        });
+    ctx.request_repaint_after(std::time::Duration::from_millis(250)); // 到时间要消失
+}